@@ -0,0 +1,101 @@
+//! Compares the job throughput of the thread pool with a pool whose workers share a
+//! mutex-guarded `mpsc` receiver, the way the pool dispatched jobs before.
+//!
+//! Run with `cargo bench --bench threadpool`.
+
+use http_server::server::ThreadPool;
+use http_server::{log, LogLevel};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREADS: usize = 8;
+const QUEUE_SIZE: usize = 64;
+const JOBS: usize = 200_000;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs the jobs on workers that take turns locking a shared receiver.
+fn mutex_pool(jobs: Vec<Job>) {
+    let (sender, receiver) = mpsc::sync_channel::<Job>(QUEUE_SIZE);
+    let receiver = Arc::new(Mutex::new(receiver));
+    let queued = Arc::new(AtomicUsize::new(0));
+    let workers: Vec<_> = (0..THREADS)
+        .map(|id| {
+            let receiver = Arc::clone(&receiver);
+            let queued = Arc::clone(&queued);
+            thread::spawn(move || loop {
+                let job = receiver.lock().unwrap().recv();
+                match job {
+                    // The same bookkeeping as the pool's workers, so only the queue differs
+                    Ok(job) => {
+                        queued.fetch_sub(1, Ordering::SeqCst);
+                        log(
+                            &format!("Worker #{} got a job; executing.", id),
+                            LogLevel::Info,
+                        );
+                        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                    }
+                    Err(_) => break,
+                }
+            })
+        })
+        .collect();
+    for job in jobs {
+        queued.fetch_add(1, Ordering::SeqCst);
+        sender.send(job).unwrap();
+    }
+    drop(sender);
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+/// Runs the jobs on the server's thread pool.
+fn crossbeam_pool(jobs: Vec<Job>) {
+    let pool = ThreadPool::new(THREADS, QUEUE_SIZE, None).unwrap();
+    for job in jobs {
+        pool.execute(job);
+    }
+}
+
+/// Returns jobs that each do a little work, and the counter they increment.
+fn jobs() -> (Vec<Job>, Arc<AtomicUsize>) {
+    let done = Arc::new(AtomicUsize::new(0));
+    let jobs = (0..JOBS)
+        .map(|i| {
+            let done = Arc::clone(&done);
+            Box::new(move || {
+                std::hint::black_box((0..100).fold(i, |sum, n| sum ^ n));
+                done.fetch_add(1, Ordering::Relaxed);
+            }) as Job
+        })
+        .collect();
+    (jobs, done)
+}
+
+fn measure(name: &str, run: fn(Vec<Job>)) -> Duration {
+    let (jobs, done) = jobs();
+    let start = Instant::now();
+    run(jobs);
+    let elapsed = start.elapsed();
+    assert_eq!(done.load(Ordering::Relaxed), JOBS);
+    println!(
+        "{:<16} {:>8.1} ms  {:>10.0} jobs/s",
+        name,
+        elapsed.as_secs_f64() * 1000.0,
+        JOBS as f64 / elapsed.as_secs_f64()
+    );
+    elapsed
+}
+
+fn main() {
+    // The workers log every job they run
+    http_server::set_log_level(None);
+    for _ in 0..3 {
+        measure("mutex receiver", mutex_pool);
+        measure("crossbeam", crossbeam_pool);
+    }
+}
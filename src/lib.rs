@@ -1,234 +1,3086 @@
 use chrono::Local;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rustls::ServerConnection;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::fs;
+use std::fs::File;
+use std::io;
 use std::io::prelude::*;
-use std::net::{TcpListener, TcpStream};
-use std::sync::Arc;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use urlencoding::decode;
 
 pub mod server;
 
-use server::{ContentHeaders, HttpContent, HttpStatus, Request, Response, ThreadPool};
+use server::{
+    directory_listing, escape_html, is_directory, read_mime_types, tls_config, upload_path,
+    AccessLog, BasicAuth, ByteRange, CachePolicy, Connection, ContentHeaders, ContentTypes,
+    FileCache, Handler, HttpContent, HttpStatus, Method, Metrics, Redirects, Request, Response,
+    ResponseSettings, ThreadPool, TlsStream, Transformer, PLAIN_TEMPLATE, SERVER_NAME,
+    STYLED_TEMPLATE,
+};
 
+/// Configuration of the server.
+/// Prefer `ServerBuilder` for creating one, which fills in the default values.
 pub struct Arguments {
-    pub directory: String,
+    pub directories: Vec<String>,
+    pub host: IpAddr,
     pub port: u16,
     pub threads: usize,
+    pub queue_size: usize,
+    pub stack_size: Option<usize>,
+    pub max_connections: Option<usize>,
+    pub watch: bool,
+    pub backlog: u32,
+    pub max_header_size: usize,
+    pub max_body_size: u64,
+    pub upload_dir: Option<String>,
+    pub auth: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub url_prefix: Option<String>,
+    pub redirects: Option<String>,
+    pub mime_types: Option<String>,
+    pub min_compress_size: usize,
+    pub compression_level: u32,
+    pub autoindex: bool,
+    pub listing_style: ListingStyle,
+    pub listing_template: Option<String>,
+    pub spa: bool,
+    pub favicon: bool,
+    pub index_files: Vec<String>,
+    pub canonical_index: bool,
+    pub download_types: Vec<String>,
+    pub charset: String,
+    pub follow_symlinks: bool,
+    pub no_cache: bool,
+    pub cache_max_age: Option<u32>,
+    pub cache_size: Option<u64>,
+    pub timeout: Duration,
+    pub keep_alive_timeout: Duration,
+    pub keep_alive_max: Option<usize>,
+    pub retry_after: Duration,
+    pub access_log: Option<String>,
+    pub log_level: Option<LogLevel>,
+    pub log_format: LogFormat,
+    pub log_color: bool,
+    pub server_header: Option<String>,
+    pub cors_origin: Option<String>,
+    pub trust_proxy: bool,
+    pub allowed_hosts: Vec<String>,
+    pub health_path: Option<String>,
+    pub metrics_path: Option<String>,
+    pub summary_interval: Option<Duration>,
+    pub nosniff: bool,
+    pub frame_options: Option<String>,
+    pub content_security_policy: Option<String>,
+    pub shutdown: Option<ShutdownHandle>,
+    pub handler: Option<Box<dyn Handler>>,
+    pub transformers: Vec<Transformer>,
 }
 
+/// Builder for the server's `Arguments`, with the same defaults as the command line
+///
+/// # Example
+/// ```no_run
+/// let config = http_server::ServerBuilder::new("public").port(8080).threads(4).build();
+/// http_server::run(config).unwrap();
+/// ```
+pub struct ServerBuilder {
+    config: Arguments,
+}
+
+impl ServerBuilder {
+    /// Returns a new builder with the default configuration.
+    ///
+    /// # Arguments
+    /// * `directory` - The directory to serve
+    pub fn new(directory: &str) -> Self {
+        ServerBuilder {
+            config: Arguments {
+                directories: vec![directory.to_string()],
+                host: IpAddr::from([127, 0, 0, 1]),
+                port: 80,
+                threads: 2,
+                queue_size: 64,
+                stack_size: None,
+                max_connections: None,
+                watch: false,
+                backlog: 128,
+                max_header_size: 8192,
+                max_body_size: 1024 * 1024,
+                upload_dir: None,
+                auth: None,
+                cert: None,
+                key: None,
+                url_prefix: None,
+                redirects: None,
+                mime_types: None,
+                min_compress_size: 1024,
+                compression_level: 6,
+                autoindex: false,
+                listing_style: ListingStyle::Plain,
+                listing_template: None,
+                spa: false,
+                favicon: false,
+                index_files: vec![String::from("index.html")],
+                canonical_index: false,
+                download_types: Vec::new(),
+                charset: String::from("UTF-8"),
+                follow_symlinks: true,
+                no_cache: false,
+                cache_max_age: None,
+                cache_size: None,
+                timeout: Duration::from_secs(30),
+                keep_alive_timeout: Duration::from_secs(5),
+                keep_alive_max: None,
+                retry_after: Duration::from_secs(5),
+                access_log: None,
+                log_level: Some(LogLevel::Info),
+                log_format: LogFormat::Text,
+                log_color: true,
+                server_header: Some(SERVER_NAME.to_string()),
+                cors_origin: None,
+                trust_proxy: false,
+                allowed_hosts: Vec::new(),
+                health_path: None,
+                metrics_path: None,
+                summary_interval: None,
+                nosniff: false,
+                frame_options: None,
+                content_security_policy: None,
+                shutdown: None,
+                handler: None,
+                transformers: Vec::new(),
+            },
+        }
+    }
+
+    /// Sets the directory to serve, replacing any additional roots.
+    pub fn directory(mut self, directory: &str) -> Self {
+        self.config.directories = vec![directory.to_string()];
+        self
+    }
+
+    /// Adds a directory to serve files from when they aren't found in the previous ones.
+    pub fn root(mut self, directory: &str) -> Self {
+        self.config.directories.push(directory.to_string());
+        self
+    }
+
+    /// Sets the address to listen on.
+    pub fn host(mut self, host: IpAddr) -> Self {
+        self.config.host = host;
+        self
+    }
+
+    /// Sets the port to listen on.
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    /// Sets the amount of worker threads. Must be greater than zero.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.config.threads = threads;
+        self
+    }
+
+    /// Sets how many connections may wait for a worker thread. Must be greater than zero.
+    pub fn queue_size(mut self, size: usize) -> Self {
+        self.config.queue_size = size;
+        self
+    }
+
+    /// Sets how many connections may be open at once, counting the ones waiting for
+    /// a worker thread. Further connections are rejected. Must be greater than zero.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.config.max_connections = Some(max);
+        self
+    }
+
+    /// Sets how many connections the operating system queues until the server accepts them.
+    /// The system may cap the value, e.g. to `net.core.somaxconn` on Linux.
+    pub fn backlog(mut self, backlog: u32) -> Self {
+        self.config.backlog = backlog;
+        self
+    }
+
+    /// Sets the stack size of each worker thread in bytes,
+    /// which must be at least `MIN_STACK_SIZE`.
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.config.stack_size = Some(size);
+        self
+    }
+
+    /// Sets the maximum size of the request headers in bytes, at least `MIN_HEADER_SIZE`.
+    pub fn max_header_size(mut self, size: usize) -> Self {
+        self.config.max_header_size = size;
+        self
+    }
+
+    /// Sets the maximum size of a request body in bytes.
+    pub fn max_body_size(mut self, size: u64) -> Self {
+        self.config.max_body_size = size;
+        self
+    }
+
+    /// Sets a folder that files can be uploaded to with `PUT` and `POST` requests.
+    /// Uploads are disabled unless this is set.
+    pub fn upload_dir(mut self, directory: &str) -> Self {
+        self.config.upload_dir = Some(directory.to_string());
+        self
+    }
+
+    /// Requires clients to log in with the given credentials, using Basic authentication.
+    ///
+    /// # Arguments
+    /// * `credentials` - The user name and password separated by a colon, e.g. `user:secret`
+    pub fn auth(mut self, credentials: &str) -> Self {
+        self.config.auth = Some(credentials.to_string());
+        self
+    }
+
+    /// Serves HTTPS instead of HTTP, with the certificate chain and private key in the given
+    /// PEM files.
+    ///
+    /// # Arguments
+    /// * `cert` - Path of the certificate chain, starting with the server's certificate
+    /// * `key` - Path of the certificate's private key
+    pub fn tls(mut self, cert: &str, key: &str) -> Self {
+        self.config.cert = Some(cert.to_string());
+        self.config.key = Some(key.to_string());
+        self
+    }
+
+    /// Sets a path prefix the server is deployed under, e.g. `/static` behind a proxy.
+    /// Requests for paths outside the prefix get a `404`.
+    pub fn url_prefix(mut self, prefix: &str) -> Self {
+        let prefix = prefix.trim_end_matches('/');
+        self.config.url_prefix = Some(prefix).filter(|p| !p.is_empty()).map(String::from);
+        self
+    }
+
+    /// Sets a file of Content-Types that override or extend the built-in ones,
+    /// see `read_mime_types` for its format.
+    pub fn mime_types(mut self, path: &str) -> Self {
+        self.config.mime_types = Some(path.to_string());
+        self
+    }
+
+    /// Reload the listing template, redirect rules and MIME types when their files change,
+    /// without restarting the server, and drop changed files in the served folders from the
+    /// file cache.
+    pub fn watch(mut self, watch: bool) -> Self {
+        self.config.watch = watch;
+        self
+    }
+
+    /// Sets a file of redirect rules, see `Redirects` for its format.
+    pub fn redirects(mut self, path: &str) -> Self {
+        self.config.redirects = Some(path.to_string());
+        self
+    }
+
+    /// Sets the compression level from 0 (none) to 9 (smallest but slowest).
+    /// Higher levels trade CPU time for less bandwidth.
+    pub fn compression_level(mut self, level: u32) -> Self {
+        self.config.compression_level = level;
+        self
+    }
+
+    /// Sets the smallest payload in bytes that is compressed. Compressing anything smaller
+    /// tends to make it larger.
+    pub fn min_compress_size(mut self, size: usize) -> Self {
+        self.config.min_compress_size = size;
+        self
+    }
+
+    /// Sets whether directories without an index.html are listed.
+    pub fn autoindex(mut self, autoindex: bool) -> Self {
+        self.config.autoindex = autoindex;
+        self
+    }
+
+    /// Sets the look of the directory listings.
+    pub fn listing_style(mut self, style: ListingStyle) -> Self {
+        self.config.listing_style = style;
+        self
+    }
+
+    /// Sets an HTML file to make the directory listings from, instead of a built-in style.
+    /// `{title}` in the file is replaced with the directory's path and `{rows}` with
+    /// the table rows of its entries.
+    pub fn listing_template(mut self, path: &str) -> Self {
+        self.config.listing_template = Some(path.to_string());
+        self
+    }
+
+    /// Sets whether the root index page is served for missing paths without a file extension,
+    /// for single-page apps that handle routing on the client side.
+    pub fn spa(mut self, spa: bool) -> Self {
+        self.config.spa = spa;
+        self
+    }
+
+    /// Sets whether a built-in icon is served for `/favicon.ico` when no served folder has one.
+    pub fn favicon(mut self, favicon: bool) -> Self {
+        self.config.favicon = favicon;
+        self
+    }
+
+    /// Sets the extensions of the files that browsers download instead of displaying,
+    /// e.g. `zip` and `pdf`.
+    pub fn download_types(mut self, extensions: &[&str]) -> Self {
+        self.config.download_types = extensions.iter().map(|ext| ext.to_string()).collect();
+        self
+    }
+
+    /// Sets the file names served for a directory, tried in order.
+    pub fn index_files(mut self, names: &[&str]) -> Self {
+        self.config.index_files = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Sets whether explicit requests for an index file, e.g. `/docs/index.html`, are
+    /// redirected to its directory, so that every page has a single URL.
+    pub fn canonical_index(mut self, canonical_index: bool) -> Self {
+        self.config.canonical_index = canonical_index;
+        self
+    }
+
+    /// Sets the character encoding of the served text files, e.g. `ISO-8859-1`.
+    pub fn charset(mut self, charset: &str) -> Self {
+        self.config.charset = charset.to_string();
+        self
+    }
+
+    /// Sets whether files may be served through symbolic links that stay in the served folder.
+    /// Links that lead outside of it are never followed.
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.config.follow_symlinks = follow;
+        self
+    }
+
+    /// Sets whether browsers are told not to cache anything, for development.
+    pub fn no_cache(mut self, no_cache: bool) -> Self {
+        self.config.no_cache = no_cache;
+        self
+    }
+
+    /// Sets how long browsers may cache any served file, in seconds,
+    /// instead of the default age for its type.
+    pub fn cache_max_age(mut self, seconds: u32) -> Self {
+        self.config.cache_max_age = Some(seconds);
+        self
+    }
+
+    /// Sets how many bytes of recently served files are kept in memory,
+    /// instead of reading them from disk for every request.
+    pub fn cache_size(mut self, bytes: u64) -> Self {
+        self.config.cache_size = Some(bytes);
+        self
+    }
+
+    /// Sets how long a client may take to send a request or receive a response.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+
+    /// Sets how long an idle connection is kept open waiting for the next request.
+    /// Must be at least one second.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.config.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// Sets how many requests a single connection may send before it is closed.
+    /// Must be greater than zero.
+    pub fn keep_alive_max(mut self, max: usize) -> Self {
+        self.config.keep_alive_max = Some(max);
+        self
+    }
+
+    /// Sets how long clients are told to wait before retrying when all workers are busy.
+    pub fn retry_after(mut self, delay: Duration) -> Self {
+        self.config.retry_after = delay;
+        self
+    }
+
+    /// Sets the file to write an access log to.
+    pub fn access_log(mut self, path: &str) -> Self {
+        self.config.access_log = Some(path.to_string());
+        self
+    }
+
+    /// Sets the lowest level of messages that are logged, or `None` to disable logging.
+    /// Unlike the other settings, the logging settings are shared by every server in the
+    /// process, and the server started last decides them.
+    pub fn log_level(mut self, level: Option<LogLevel>) -> Self {
+        self.config.log_level = level;
+        self
+    }
+
+    /// Sets the output format of log messages.
+    pub fn log_format(mut self, format: LogFormat) -> Self {
+        self.config.log_format = format;
+        self
+    }
+
+    /// Sets whether text log messages are colored with ANSI escape codes.
+    pub fn log_color(mut self, color: bool) -> Self {
+        self.config.log_color = color;
+        self
+    }
+
+    /// Sets the value of the `Server` response header, or `None` to leave the header out.
+    pub fn server_header(mut self, value: Option<&str>) -> Self {
+        self.config.server_header = value.map(String::from);
+        self
+    }
+
+    /// Sets the origin allowed to fetch the served files cross-origin, e.g. `*`.
+    pub fn cors_origin(mut self, origin: &str) -> Self {
+        self.config.cors_origin = Some(origin.to_string());
+        self
+    }
+
+    /// Sets whether the client address is taken from the `X-Forwarded-For` header,
+    /// for running behind a reverse proxy. Clients can forge the header, so this
+    /// should only be enabled when every request comes through the proxy.
+    pub fn trust_proxy(mut self, trust: bool) -> Self {
+        self.config.trust_proxy = trust;
+        self
+    }
+
+    /// Adds a host name that requests may be sent to. If no hosts are added,
+    /// requests are accepted regardless of their `Host` header.
+    pub fn allowed_host(mut self, host: &str) -> Self {
+        self.config.allowed_hosts.push(host.to_string());
+        self
+    }
+
+    /// Sets the path of a health check endpoint that always responds with `200 OK`.
+    pub fn health_path(mut self, path: &str) -> Self {
+        self.config.health_path = Some(path.to_string());
+        self
+    }
+
+    /// Sets the path of an endpoint listing the server's traffic counters.
+    pub fn metrics_path(mut self, path: &str) -> Self {
+        self.config.metrics_path = Some(path.to_string());
+        self
+    }
+
+    /// Sets how often a summary of the traffic counters is logged.
+    pub fn summary_interval(mut self, interval: Duration) -> Self {
+        self.config.summary_interval = Some(interval);
+        self
+    }
+
+    /// Sets whether browsers are told not to guess the type of the served files.
+    pub fn nosniff(mut self, nosniff: bool) -> Self {
+        self.config.nosniff = nosniff;
+        self
+    }
+
+    /// Sets the `X-Frame-Options` header, e.g. `SAMEORIGIN`.
+    pub fn frame_options(mut self, value: &str) -> Self {
+        self.config.frame_options = Some(value.to_string());
+        self
+    }
+
+    /// Sets the `Content-Security-Policy` header.
+    pub fn content_security_policy(mut self, policy: &str) -> Self {
+        self.config.content_security_policy = Some(policy.to_string());
+        self
+    }
+
+    /// Sets a handle for stopping the server from another thread, see `ShutdownHandle`.
+    pub fn shutdown_handle(mut self, handle: &ShutdownHandle) -> Self {
+        self.config.shutdown = Some(handle.clone());
+        self
+    }
+
+    /// Sets a handler for requests that aren't served from the files, see `Handler`.
+    pub fn handler<H: Handler + 'static>(mut self, handler: H) -> Self {
+        self.config.handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Adds a transformer that can change every response before it is sent, e.g. to add
+    /// headers. Transformers are applied in the order they were added.
+    pub fn transformer<F>(mut self, transformer: F) -> Self
+    where
+        F: Fn(&Request, &mut Response) + Send + Sync + 'static,
+    {
+        self.config.transformers.push(Box::new(transformer));
+        self
+    }
+
+    /// Returns the built configuration.
+    pub fn build(self) -> Arguments {
+        self.config
+    }
+}
+
+/// Handle for stopping a running server, for programs that embed it
+///
+/// After `stop` is called, the server stops accepting connections, lets the queued
+/// requests finish and returns from `run`. Clones of a handle stop the same server.
+/// The server leaves the process's signal handlers alone, so an embedding program
+/// decides itself whether e.g. Ctrl-C stops the server.
+///
+/// # Example
+/// ```no_run
+/// let handle = http_server::ShutdownHandle::new();
+/// let config = http_server::ServerBuilder::new("public")
+///     .shutdown_handle(&handle)
+///     .build();
+/// let server = std::thread::spawn(move || http_server::run(config));
+/// handle.stop();
+/// server.join().unwrap().unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    stopped: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+    /// Returns a new handle that hasn't been stopped.
+    pub fn new() -> Self {
+        ShutdownHandle::default()
+    }
+
+    /// Asks the server to shut down. It notices within a fraction of a second.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether `stop` has been called.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+}
+
+/// State shared between all connections
+struct ServerState {
+    config: Arguments,
+    response_settings: ResponseSettings,
+    content_types: RwLock<ContentTypes>,
+    access_log: Option<AccessLog>,
+    listing_template: RwLock<Cow<'static, str>>,
+    redirects: RwLock<Option<Redirects>>,
+    auth: Option<BasicAuth>,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    single_file: Option<String>,
+    file_cache: Option<FileCache>,
+    metrics: Metrics,
+    open_connections: AtomicUsize,
+}
+
+impl ServerState {
+    /// Returns the content type specific response headers for the file, according to the
+    /// configured Content-Types.
+    fn content_headers(&self, content: &HttpContent) -> ContentHeaders<'static> {
+        match self.content_types.read() {
+            Ok(types) => content.content_headers(&types),
+            Err(_) => content.content_headers(&ContentTypes::default()),
+        }
+    }
+}
+
+/// A request being answered, along with what its response depends on
+///
+/// * `state` - State shared between all connections
+/// * `request` - The request
+/// * `include_body` - Whether the response has a body, i.e. the request isn't `HEAD`
+/// * `keep_alive` - Whether the connection may stay open after the response
+#[derive(Clone, Copy)]
+struct Exchange<'a> {
+    state: &'a ServerState,
+    request: &'a Request<'a>,
+    include_body: bool,
+    keep_alive: bool,
+}
+
+impl Exchange<'_> {
+    /// Returns the exchange with the connection closed after the response.
+    fn closing(self) -> Self {
+        Exchange {
+            keep_alive: false,
+            ..self
+        }
+    }
+}
+
+/// Counts a connection as open for as long as it is alive,
+/// so it is released even if handling the connection panics
+struct OpenConnection {
+    state: Arc<ServerState>,
+}
+
+impl OpenConnection {
+    fn new(state: Arc<ServerState>) -> Self {
+        state.open_connections.fetch_add(1, Ordering::SeqCst);
+        OpenConnection { state }
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.state.open_connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Summary of a response that was sent to the client
+///
+/// * `status` - The response's HTTP status code
+/// * `bytes` - The number of body bytes sent
+/// * `keep_alive` - Whether the connection stays open for further requests
+struct Sent {
+    status: u16,
+    bytes: u64,
+    keep_alive: bool,
+}
+
+/// Smallest allowed stack size of the worker threads, enough for handling a request
+pub const MIN_STACK_SIZE: usize = 64 * 1024;
+
+/// Smallest allowed size limit of the request headers, enough for the shortest request
+pub const MIN_HEADER_SIZE: usize = "GET / HTTP/1.1\r\n\r\n".len();
+
+/// How long the accept loop sleeps between checks for new connections
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Files larger than this are streamed from disk without compression,
+/// instead of being read into memory
+const STREAM_THRESHOLD: u64 = 1024 * 1024;
+
+/// Content encodings that can be served from precompressed files next to the requested
+/// file, and the extensions of those files, in order of preference
+const PRECOMPRESSED_EXTENSIONS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// Content encodings the server can compress responses with, in order of preference
+const DYNAMIC_ENCODINGS: [&str; 3] = ["br", "gzip", "deflate"];
+
+/// Icon served for `/favicon.ico` with the `favicon` option, when no served folder has one
+const DEFAULT_FAVICON: &[u8] = include_bytes!("favicon.ico");
+
+/// How long browsers may cache the built-in favicon, in seconds
+const FAVICON_CACHE_AGE: u32 = 7 * 86400;
+
+/// Methods the server responds to, as listed in the `Allow` header
+const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// Methods the server responds to when uploads are enabled
+const UPLOAD_METHODS: &str = "GET, HEAD, OPTIONS, PUT, POST";
+
+/// Name of the protected area in Basic authentication challenges, shown by some browsers
+const AUTH_REALM: &str = "http-server";
+
+/// How long browsers may cache the result of a CORS preflight request, in seconds
+const CORS_MAX_AGE: u64 = 86400;
+
+#[derive(Clone, Copy)]
 pub enum LogLevel {
     Info,
     ClientError,
     ServerError,
 }
 
+/// Look of the directory listings
+#[derive(Clone, Copy)]
+pub enum ListingStyle {
+    /// An unstyled table
+    Plain,
+    /// A styled table with columns that sort when clicked
+    Styled,
+}
+
+/// Output format of the log messages
+#[derive(Clone, Copy)]
+pub enum LogFormat {
+    /// Colored, human-readable lines
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Threshold value that disables all logging
+const LOG_QUIET: u8 = 3;
+
+/// Messages below this `LogLevel` are not logged
+static LOG_THRESHOLD: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Whether log messages are written as JSON instead of text
+static LOG_JSON: AtomicBool = AtomicBool::new(false);
+
+/// Whether text log messages are colored with ANSI escape codes
+static LOG_COLOR: AtomicBool = AtomicBool::new(true);
+
+/// Sets the lowest level of messages that are logged.
+/// The setting is process-wide, so it applies to every server running in the process.
+///
+/// # Arguments
+/// * `level` - The lowest logged level, or `None` to disable logging
+pub fn set_log_level(level: Option<LogLevel>) {
+    let threshold = level.map_or(LOG_QUIET, |level| level as u8);
+    LOG_THRESHOLD.store(threshold, Ordering::Relaxed);
+}
+
+/// Sets the output format of log messages, for every server running in the process.
+pub fn set_log_format(format: LogFormat) {
+    LOG_JSON.store(matches!(format, LogFormat::Json), Ordering::Relaxed);
+}
+
+/// Sets whether text log messages are colored with ANSI escape codes, for every server
+/// running in the process.
+pub fn set_log_color(color: bool) {
+    LOG_COLOR.store(color, Ordering::Relaxed);
+}
+
 pub fn log(msg: &str, level: LogLevel) {
-    let (id, color) = match level {
+    log_with(msg, level, &[]);
+}
+
+/// Logs a message with additional named fields.
+/// In the text format, each field is printed on its own line after the message.
+///
+/// # Arguments
+/// * `msg` - The message to log
+/// * `level` - The message's level
+/// * `fields` - Names and values of the additional fields
+pub fn log_with(msg: &str, level: LogLevel, fields: &[(&str, &str)]) {
+    if (level as u8) < LOG_THRESHOLD.load(Ordering::Relaxed) {
+        return;
+    }
+    if LOG_JSON.load(Ordering::Relaxed) {
+        let name = match level {
+            LogLevel::Info => "info",
+            LogLevel::ClientError => "warn",
+            LogLevel::ServerError => "error",
+        };
+        let mut line = format!(
+            "{{\"level\":\"{}\",\"timestamp\":\"{}\",\"message\":{}",
+            name,
+            Local::now().to_rfc3339(),
+            json_string(msg)
+        );
+        if let Some(id) = current_request_id() {
+            line.push_str(&format!(",\"request_id\":{}", json_string(&id)));
+        }
+        for (key, value) in fields {
+            line.push_str(&format!(",{}:{}", json_string(key), json_string(value)));
+        }
+        line.push('}');
+        println!("{}", line);
+        return;
+    }
+
+    let color = LOG_COLOR.load(Ordering::Relaxed);
+    let request_id = current_request_id();
+    println!(
+        "{}",
+        format_text(msg, level, fields, request_id.as_deref(), color)
+    );
+}
+
+/// Returns a log message in the human-readable text format.
+///
+/// # Arguments
+/// * `msg` - The message to log
+/// * `level` - The message's level
+/// * `fields` - Names and values of the additional fields
+/// * `request_id` - ID of the request the message is about, if any
+/// * `color` - Whether to color the message's prefix with ANSI escape codes
+fn format_text(
+    msg: &str,
+    level: LogLevel,
+    fields: &[(&str, &str)],
+    request_id: Option<&str>,
+    color: bool,
+) -> String {
+    let (id, start) = match level {
         LogLevel::Info => ("INFO", "\x1B[33;94m"),
         LogLevel::ClientError => ("CLNT", "\x1B[33;93m"),
         LogLevel::ServerError => ("SERV", "\x1B[33;91m"),
     };
-    println!("{}[{}@{:?}]\x1B[33;0m {}", color, id, Local::now(), msg);
+    let (start, end) = if color {
+        (start, "\x1B[33;0m")
+    } else {
+        ("", "")
+    };
+    let mut text = format!("{}[{}@{:?}]{} ", start, id, Local::now(), end);
+    if let Some(request_id) = request_id {
+        text.push_str(&format!("[{}] ", request_id));
+    }
+    text.push_str(msg);
+    for (key, value) in fields {
+        text.push_str(&format!("\n{}: {}", key, value));
+    }
+    text
+}
+
+/// Number used for the ID of the next request that doesn't bring its own
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Longest request ID accepted from a client's `X-Request-Id` header
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+
+thread_local! {
+    /// ID of the request being handled on this thread, included in its log messages
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+
+    /// How many more requests the connection being handled on this thread may send,
+    /// or `None` if there is no limit
+    static REMAINING_REQUESTS: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Returns the ID of the request being handled on this thread, if any.
+fn current_request_id() -> Option<String> {
+    REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+/// Sets the ID of the request being handled on this thread.
+fn set_request_id(id: Option<String>) {
+    REQUEST_ID.with(|current| *current.borrow_mut() = id);
+}
+
+/// Returns the ID for a request: the client's own `X-Request-Id` if it is reasonable,
+/// or a newly generated one.
+fn request_id(request: &Request) -> String {
+    let valid = |id: &&str| {
+        !id.is_empty()
+            && id.len() <= MAX_REQUEST_ID_LENGTH
+            && id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+    };
+    match request.get_header("X-Request-Id").filter(valid) {
+        Some(id) => id.to_string(),
+        None => format!("{:08x}", NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)),
+    }
 }
 
-pub fn run(config: Arguments) {
-    log(
-        &format!("Starting server on port {}", config.port),
+/// Returns the given text as a quoted and escaped JSON string.
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Starts the server and handles incoming connections.
+/// Returns an `io::Error` if the configuration is invalid or the server could not be started.
+/// Errors with individual connections are logged instead.
+pub fn run(config: Arguments) -> io::Result<()> {
+    run_with(config, |_| {})
+}
+
+/// Starts the server like `run`, calling `on_listening` with the bound address once
+/// connections are being accepted. With port 0, the address has the port the
+/// operating system picked.
+///
+/// Several servers can run in the same process, each with its own configuration, except
+/// for logging: the log level, format and color are process-wide settings, and each server
+/// sets them from its configuration when it starts, replacing those of the servers already
+/// running.
+///
+/// # Example
+/// ```no_run
+/// let config = http_server::ServerBuilder::new("public").port(0).build();
+/// http_server::run_with(config, |address| println!("Serving on port {}", address.port()))
+///     .unwrap();
+/// ```
+pub fn run_with<F: FnOnce(SocketAddr)>(config: Arguments, on_listening: F) -> io::Result<()> {
+    let (config, single_file) = single_file_config(config);
+    set_log_level(config.log_level);
+    set_log_format(config.log_format);
+    set_log_color(config.log_color);
+    if let Err(err) = validate_config(&config) {
+        log(
+            &format!("Invalid configuration: {}", err),
+            LogLevel::ServerError,
+        );
+        return Err(err);
+    }
+    // A missing index page usually means the wrong directory was given
+    let has_index = config.directories.iter().any(|directory| {
+        config
+            .index_files
+            .iter()
+            .any(|name| Path::new(directory).join(name).is_file())
+    });
+    if !has_index && !config.spa && !config.autoindex {
+        log(
+            &format!(
+                "None of the served directories contain {}, so / will not be found",
+                config.index_files.join(" or ")
+            ),
+            LogLevel::ClientError,
+        );
+    }
+    let address = SocketAddr::new(config.host, config.port);
+    log(&format!("Starting server on {}", address), LogLevel::Info);
+    let listener = match bind(address, config.backlog) {
+        Ok(listener) => listener,
+        Err(err) => {
+            log(
+                &format!("Could not bind to {}: {}", address, err),
+                LogLevel::ServerError,
+            );
+            return Err(err);
+        }
+    };
+    // The bound address differs from the requested one when binding to port 0
+    let bound = listener.local_addr()?;
+    log(&format!("Listening on {}", bound), LogLevel::Info);
+    let access_log = match &config.access_log {
+        Some(path) => match AccessLog::open(path) {
+            Ok(access_log) => Some(access_log),
+            Err(err) => {
+                log(
+                    &format!("Could not open access log {}: {}", path, err),
+                    LogLevel::ServerError,
+                );
+                return Err(err);
+            }
+        },
+        None => None,
+    };
+    let listing_template = load_listing_template(&config)?;
+    let tls = load_tls_config(&config)?;
+    let redirects = load_redirects(&config)?;
+    let content_types = ContentTypes {
+        charset: config.charset.clone(),
+        download_types: config.download_types.clone(),
+        mime_types: load_mime_types(&config)?,
+    };
+    let watch = if config.watch {
+        match start_watching(&config) {
+            Ok(watch) => Some(watch),
+            Err(err) => {
+                log(
+                    &format!("Could not watch for changes: {}", err),
+                    LogLevel::ServerError,
+                );
+                return Err(err);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Poll for connections so the shutdown flag can be checked between them
+    listener.set_nonblocking(true)?;
+
+    let pool = match ThreadPool::new(config.threads, config.queue_size, config.stack_size) {
+        Ok(pool) => pool,
+        Err(err) => {
+            log(
+                &format!("Could not start the worker threads: {}", err),
+                LogLevel::ServerError,
+            );
+            return Err(err);
+        }
+    };
+
+    let file_cache = config.cache_size.map(FileCache::new);
+    let auth = config.auth.as_deref().map(BasicAuth::new);
+
+    let state = Arc::new(ServerState {
+        response_settings: response_settings(&config),
+        content_types: RwLock::new(content_types),
+        config,
+        access_log,
+        listing_template: RwLock::new(listing_template),
+        redirects: RwLock::new(redirects),
+        auth,
+        tls,
+        single_file,
+        file_cache,
+        metrics: Metrics::new(),
+        open_connections: AtomicUsize::new(0),
+    });
+
+    on_listening(bound);
+
+    let mut last_summary = Instant::now();
+    while !shutdown_requested(&state.config) {
+        if let Some(interval) = state.config.summary_interval {
+            if last_summary.elapsed() >= interval {
+                log(&state.metrics.summary(), LogLevel::Info);
+                last_summary = Instant::now();
+            }
+        }
+        if let Some(watch) = &watch {
+            apply_changes(&state, watch);
+        }
+        let state = Arc::clone(&state);
+        match listener.accept() {
+            Ok((mut stream, _)) => {
+                if let Err(err) = stream.set_nonblocking(false) {
+                    log(
+                        &format!("Could not configure client stream: {}", err),
+                        LogLevel::ServerError,
+                    );
+                    continue;
+                }
+                // Only this loop queues jobs, so the queue can't fill up after the check
+                if pool.is_full() {
+                    log(
+                        "All workers are busy, rejecting connection",
+                        LogLevel::ServerError,
+                    );
+                    turn_away(&mut stream, &state);
+                    continue;
+                }
+                // Only this loop opens connections, so the count can't grow after the check
+                let open = state.open_connections.load(Ordering::SeqCst);
+                if state.config.max_connections.is_some_and(|max| open >= max) {
+                    log(
+                        "Too many open connections, rejecting connection",
+                        LogLevel::ServerError,
+                    );
+                    turn_away(&mut stream, &state);
+                    continue;
+                }
+                let connection = OpenConnection::new(Arc::clone(&state));
+                pool.execute(move || {
+                    let _connection = connection;
+                    handle_connection(stream, state);
+                });
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(err) => {
+                log(
+                    &format!(" Client failed to connect: {}", err),
+                    LogLevel::ClientError,
+                );
+            }
+        }
+    }
+
+    log(
+        "Shutdown requested, no longer accepting connections",
+        LogLevel::Info,
+    );
+    // Dropping the pool lets queued requests finish before the workers are joined
+    drop(pool);
+    log(&state.metrics.summary(), LogLevel::Info);
+    log("Server stopped", LogLevel::Info);
+    Ok(())
+}
+
+/// Returns the directory listing template: the configured template file, or the page of
+/// the configured listing style. Returns `io::Error` if the file could not be read.
+fn load_listing_template(config: &Arguments) -> io::Result<Cow<'static, str>> {
+    match &config.listing_template {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(template) => Ok(Cow::Owned(template)),
+            Err(err) => {
+                log(
+                    &format!("Could not read listing template {}: {}", path, err),
+                    LogLevel::ServerError,
+                );
+                Err(err)
+            }
+        },
+        None => match config.listing_style {
+            ListingStyle::Plain => Ok(Cow::Borrowed(PLAIN_TEMPLATE)),
+            ListingStyle::Styled => Ok(Cow::Borrowed(STYLED_TEMPLATE)),
+        },
+    }
+}
+
+/// Returns the configured redirect rules, or `None` if there is no rules file.
+/// Returns `io::Error` if the file could not be read or has an invalid rule.
+fn load_redirects(config: &Arguments) -> io::Result<Option<Redirects>> {
+    match &config.redirects {
+        Some(path) => match Redirects::load(path) {
+            Ok(redirects) => Ok(Some(redirects)),
+            Err(err) => {
+                log(
+                    &format!("Could not read redirect rules {}: {}", path, err),
+                    LogLevel::ServerError,
+                );
+                Err(err)
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+/// Returns the TLS configuration from the configured certificate and key, or `None` if
+/// the server doesn't use TLS.
+fn load_tls_config(config: &Arguments) -> io::Result<Option<Arc<rustls::ServerConfig>>> {
+    match (&config.cert, &config.key) {
+        (Some(cert), Some(key)) => tls_config(cert, key).map(Some).map_err(|err| {
+            log(
+                &format!(
+                    "Could not load the certificate {} and key {}: {}",
+                    cert, key, err
+                ),
+                LogLevel::ServerError,
+            );
+            err
+        }),
+        _ => Ok(None),
+    }
+}
+
+/// Returns the configured Content-Type overrides, or none if there is no MIME types file.
+/// Returns `io::Error` if the file could not be read or has an invalid line.
+fn load_mime_types(config: &Arguments) -> io::Result<Vec<(String, String)>> {
+    match &config.mime_types {
+        Some(path) => read_mime_types(path).map_err(|err| {
+            log(
+                &format!("Could not read MIME types {}: {}", path, err),
+                LogLevel::ServerError,
+            );
+            err
+        }),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// The served folders and configuration files being watched for changes, with the
+/// `watch` option
+///
+/// * `_watcher` - Keeps the watches in place until dropped
+/// * `events` - Changes reported by the watcher
+/// * `files` - The configuration files that are reloaded when they change
+/// * `directories` - The served folders as configured, along with their canonical paths
+struct Watch {
+    _watcher: RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<Event>>,
+    files: Vec<WatchedFile>,
+    directories: Vec<(String, PathBuf)>,
+}
+
+/// A configuration file that is reloaded when it changes, with the `watch` option
+///
+/// * `kind` - What the file configures
+/// * `path` - Path of the file as configured
+/// * `watched` - Canonical path of the file, as the watcher reports its changes
+struct WatchedFile {
+    kind: ConfigFile,
+    path: String,
+    watched: PathBuf,
+}
+
+/// The configuration files that can be reloaded
+enum ConfigFile {
+    ListingTemplate,
+    Redirects,
+    MimeTypes,
+}
+
+/// Starts watching the served folders and the configuration files for changes.
+/// Returns `io::Error` if a folder could not be watched.
+fn start_watching(config: &Arguments) -> io::Result<Watch> {
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(io::Error::other)?;
+
+    let mut directories = Vec::new();
+    for directory in &config.directories {
+        let canonical = fs::canonicalize(directory)?;
+        watcher
+            .watch(&canonical, RecursiveMode::Recursive)
+            .map_err(io::Error::other)?;
+        directories.push((directory.clone(), canonical));
+    }
+
+    let configured = vec![
+        (ConfigFile::ListingTemplate, &config.listing_template),
+        (ConfigFile::Redirects, &config.redirects),
+        (ConfigFile::MimeTypes, &config.mime_types),
+    ];
+    let mut files = Vec::new();
+    for (kind, path) in configured {
+        let path = match path {
+            Some(path) => path,
+            None => continue,
+        };
+        let name = match Path::new(path).file_name() {
+            Some(name) => name,
+            None => continue,
+        };
+        // Editors often save by replacing the file, which ends a watch on the file itself
+        let folder = Path::new(path)
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let folder = fs::canonicalize(folder)?;
+        if !directories
+            .iter()
+            .any(|(_, served)| folder.starts_with(served))
+        {
+            watcher
+                .watch(&folder, RecursiveMode::NonRecursive)
+                .map_err(io::Error::other)?;
+        }
+        files.push(WatchedFile {
+            kind,
+            path: path.clone(),
+            watched: folder.join(name),
+        });
+    }
+
+    Ok(Watch {
+        _watcher: watcher,
+        events,
+        files,
+        directories,
+    })
+}
+
+/// Applies the changes reported since the last call. Changed configuration files are
+/// reloaded, and changed files are dropped from the file cache so they are read again.
+fn apply_changes(state: &ServerState, watch: &Watch) {
+    let mut changed = Vec::new();
+    for event in watch.events.try_iter() {
+        match event {
+            Ok(event) => {
+                if matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                ) {
+                    changed.extend(event.paths);
+                }
+            }
+            Err(err) => log(
+                &format!("Could not watch for changes: {}", err),
+                LogLevel::ServerError,
+            ),
+        }
+    }
+    // Saving a file usually takes several events
+    changed.sort();
+    changed.dedup();
+
+    for file in &watch.files {
+        if changed.contains(&file.watched) {
+            reload(state, file);
+        }
+    }
+    if let Some(cache) = &state.file_cache {
+        for path in &changed {
+            for (directory, canonical) in &watch.directories {
+                let served = match path.strip_prefix(canonical) {
+                    Ok(relative) => Path::new(directory).join(relative),
+                    Err(_) => continue,
+                };
+                if cache.invalidate(&served) {
+                    log(
+                        &format!("Dropped {} from the file cache", served.display()),
+                        LogLevel::Info,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Reloads the configuration file. A file that can't be loaded leaves the previous
+/// configuration in place.
+fn reload(state: &ServerState, file: &WatchedFile) {
+    let reloaded = match file.kind {
+        ConfigFile::ListingTemplate => load_listing_template(&state.config).map(|template| {
+            if let Ok(mut current) = state.listing_template.write() {
+                *current = template;
+            }
+        }),
+        ConfigFile::Redirects => load_redirects(&state.config).map(|redirects| {
+            if let Ok(mut current) = state.redirects.write() {
+                *current = redirects;
+            }
+        }),
+        ConfigFile::MimeTypes => load_mime_types(&state.config).map(|types| {
+            if let Ok(mut current) = state.content_types.write() {
+                current.mime_types = types;
+            }
+        }),
+    };
+    if reloaded.is_ok() {
+        log(&format!("Reloaded {}", file.path), LogLevel::Info);
+    }
+}
+
+/// Returns a listener bound to the address, with room for `backlog` connections waiting
+/// to be accepted. Returns `io::Error` if the socket could not be set up.
+fn bind(address: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    // As with `TcpListener::bind`, so a restarted server can take the port back right away
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(i32::try_from(backlog).unwrap_or(i32::MAX))?;
+    Ok(socket.into())
+}
+
+/// Returns the settings the configuration applies to every response.
+fn response_settings(config: &Arguments) -> ResponseSettings {
+    ResponseSettings {
+        server_header: config.server_header.clone(),
+        headers: configured_headers(config),
+        cache_policy: match (config.no_cache, config.cache_max_age) {
+            (true, _) => CachePolicy::NoStore,
+            (false, Some(age)) => CachePolicy::MaxAge(age),
+            (false, None) => CachePolicy::PerType,
+        },
+    }
+}
+
+/// Returns the headers the configuration adds to every response.
+fn configured_headers(config: &Arguments) -> Vec<(&'static str, String)> {
+    let mut headers = Vec::new();
+    if let Some(origin) = &config.cors_origin {
+        headers.push(("Access-Control-Allow-Origin", origin.clone()));
+    }
+    if config.nosniff {
+        headers.push(("X-Content-Type-Options", String::from("nosniff")));
+    }
+    if let Some(value) = &config.frame_options {
+        headers.push(("X-Frame-Options", value.clone()));
+    }
+    if let Some(policy) = &config.content_security_policy {
+        headers.push(("Content-Security-Policy", policy.clone()));
+    }
+    headers
+}
+
+/// Returns the configuration for serving a single file, if the first served directory is
+/// actually a file, along with the file's name. The file is then served as the index page
+/// of its parent directory, and `handle_request` keeps the rest of that directory hidden.
+fn single_file_config(mut config: Arguments) -> (Arguments, Option<String>) {
+    let file = match config.directories.first() {
+        Some(path) if Path::new(path).is_file() => Path::new(path),
+        _ => return (config, None),
+    };
+    let name = match file.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return (config, None),
+    };
+    let parent = match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_string_lossy().into_owned(),
+        _ => String::from("."),
+    };
+    config.directories[0] = parent;
+    config.index_files = vec![name.clone()];
+    config.autoindex = false;
+    config.spa = false;
+    config.canonical_index = false;
+    (config, Some(name))
+}
+
+/// Checks the configuration for values the server can't start with.
+fn validate_config(config: &Arguments) -> io::Result<()> {
+    if config.threads == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "at least one thread is required",
+        ));
+    }
+    if config.queue_size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the queue size must be at least one",
+        ));
+    }
+    if config.compression_level > 9 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the compression level must be between 0 and 9",
+        ));
+    }
+    if config.timeout.is_zero() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the timeout must be greater than zero",
+        ));
+    }
+    if config.cert.is_some() != config.key.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "TLS requires both a certificate and a private key",
+        ));
+    }
+    if config.keep_alive_timeout.as_secs() == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the keep-alive timeout must be at least one second",
+        ));
+    }
+    if config.keep_alive_max == Some(0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a connection must be allowed at least one request",
+        ));
+    }
+    if config.max_connections == Some(0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the connection limit must be at least one",
+        ));
+    }
+    if config.stack_size.is_some_and(|size| size < MIN_STACK_SIZE) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the stack size must be at least {} bytes", MIN_STACK_SIZE),
+        ));
+    }
+    if config.max_header_size < MIN_HEADER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the header size limit must be at least {} bytes",
+                MIN_HEADER_SIZE
+            ),
+        ));
+    }
+    if config.directories.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "at least one directory to serve is required",
+        ));
+    }
+    if config.auth.as_ref().is_some_and(|auth| !auth.contains(':')) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the credentials must be a user name and a password separated by a colon",
+        ));
+    }
+    if let Some(prefix) = &config.url_prefix {
+        if !prefix.starts_with('/') || prefix.ends_with('/') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the URL prefix must start with a slash and not end with one",
+            ));
+        }
+    }
+    if let Some(directory) = &config.upload_dir {
+        if !Path::new(directory).is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("the upload folder {} is not a directory", directory),
+            ));
+        }
+    }
+    for directory in &config.directories {
+        if !Path::new(directory).is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a directory", directory),
+            ));
+        }
+        if let Err(err) = fs::read_dir(directory) {
+            return Err(io::Error::new(
+                err.kind(),
+                format!("{} can't be read: {}", directory, err),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether the server has been asked to shut down through its `ShutdownHandle`.
+fn shutdown_requested(config: &Arguments) -> bool {
+    config
+        .shutdown
+        .as_ref()
+        .is_some_and(ShutdownHandle::is_stopped)
+}
+
+/// Buffers for reading requests, reused by every connection handled on the same thread
+///
+/// * `pending` - Bytes received but not yet consumed, e.g. from pipelined requests
+/// * `headers` - The header block of the request being handled
+#[derive(Default)]
+struct ReadBuffers {
+    pending: Vec<u8>,
+    headers: Vec<u8>,
+}
+
+thread_local! {
+    static READ_BUFFERS: RefCell<ReadBuffers> = RefCell::new(ReadBuffers::default());
+}
+
+fn handle_connection(mut stream: TcpStream, state: Arc<ServerState>) {
+    READ_BUFFERS.with(|buffers| {
+        let mut buffers = buffers.borrow_mut();
+        let ReadBuffers { pending, headers } = &mut *buffers;
+        // Nothing from the previous connection may be mistaken for this one's data
+        pending.clear();
+        headers.clear();
+        match &state.tls {
+            // The handshake happens on the first read, within the request timeouts
+            Some(tls) => match ServerConnection::new(Arc::clone(tls)) {
+                Ok(connection) => {
+                    let mut stream = TlsStream::new(connection, stream);
+                    serve_connection(&mut stream, &state, pending, headers);
+                    stream.close();
+                }
+                Err(err) => log(
+                    &format!("Could not start a TLS session: {}", err),
+                    LogLevel::ServerError,
+                ),
+            },
+            None => serve_connection(&mut stream, &state, pending, headers),
+        }
+    });
+    set_request_id(None);
+}
+
+/// Handles requests from a client until the connection is closed.
+///
+/// # Arguments
+/// * `stream` - The client connection
+/// * `state` - State shared between all connections
+/// * `pending` - Empty buffer for bytes received but not yet consumed
+/// * `headers` - Empty buffer for the header block of each request
+fn serve_connection(
+    stream: &mut dyn Connection,
+    state: &ServerState,
+    pending: &mut Vec<u8>,
+    headers: &mut Vec<u8>,
+) {
+    let config = &state.config;
+    let _active = state.metrics.track_connection();
+    if let Err(err) = stream.socket().set_write_timeout(Some(config.timeout)) {
+        log(
+            &format!("Could not configure client stream: {}", err),
+            LogLevel::ServerError,
+        );
+        return;
+    }
+    // The first request gets the full timeout, later ones only the keep-alive timeout
+    let mut idle_timeout = config.timeout;
+    let mut served = 0;
+
+    loop {
+        set_request_id(None);
+        match read_headers(
+            stream,
+            pending,
+            headers,
+            config.max_header_size,
+            idle_timeout,
+            config.timeout,
+        ) {
+            HeaderRead::Complete => {}
+            HeaderRead::Closed => return,
+            HeaderRead::TimedOut => {
+                log("Client timed out sending a request", LogLevel::ClientError);
+                return;
+            }
+            HeaderRead::Invalid => {
+                log("Client sent malformed stream", LogLevel::ClientError);
+                reject(stream, state, HttpStatus::BadRequest);
+                return;
+            }
+        };
+
+        let buffer_str = String::from_utf8_lossy(headers);
+        let request = match Request::parse(&buffer_str) {
+            Some(x) => x,
+            None => {
+                reject(stream, state, HttpStatus::BadRequest);
+                return;
+            }
+        };
+        set_request_id(Some(request_id(&request)));
+
+        // The body is not used, but must be consumed to reach the next request
+        let body_length = match request.get_header("Content-Length") {
+            Some(value) => match value.parse::<u64>() {
+                Ok(length) => length,
+                Err(_) => {
+                    reject(stream, state, HttpStatus::BadRequest);
+                    return;
+                }
+            },
+            None => 0,
+        };
+        if body_length > config.max_body_size {
+            log(
+                "Client sent a body that is too large",
+                LogLevel::ClientError,
+            );
+            reject(stream, state, HttpStatus::PayloadTooLarge);
+            return;
+        }
+        if let Some(expectation) = request.get_header("Expect") {
+            if !expectation.eq_ignore_ascii_case("100-continue") {
+                log(
+                    "Client sent an expectation that can't be met",
+                    LogLevel::ClientError,
+                );
+                reject(stream, state, HttpStatus::ExpectationFailed);
+                return;
+            }
+        }
+        // The client waits for an interim response before sending the body, unless it has
+        // already begun. The interim response is only sent once the body is going to be read
+        let awaits_continue = body_length > 0
+            && pending.is_empty()
+            && request.protocol == "HTTP/1.1"
+            && request
+                .get_header("Expect")
+                .is_some_and(|expectation| expectation.eq_ignore_ascii_case("100-continue"));
+        // An upload's body is read while handling the request, any other body is skipped
+        let upload =
+            config.upload_dir.is_some() && matches!(request.method, Method::Put | Method::Post);
+        let body = if upload {
+            Some(PendingBody {
+                pending: &mut *pending,
+                length: body_length,
+                awaits_continue,
+            })
+        } else if awaits_continue {
+            // The body is never sent, so the connection is closed after responding
+            None
+        } else if discard_body(stream, pending, body_length).is_err() {
+            log("Client sent an incomplete body", LogLevel::ClientError);
+            return;
+        } else {
+            None
+        };
+
+        // Requests the connection may send after this one, if they are limited
+        served += 1;
+        let remaining = config.keep_alive_max.map(|max| max.saturating_sub(served));
+        REMAINING_REQUESTS.with(|current| current.set(remaining));
+
+        // A chunked body can't be delimited, so the connection is closed after responding
+        let keep_alive = request.get_header("Transfer-Encoding").is_none()
+            && (upload || !awaits_continue)
+            && keeps_alive(&request)
+            && remaining != Some(0);
+
+        state.metrics.record_request();
+        let sent = match handle_request(stream, state, &request, body, keep_alive) {
+            Some(sent) => sent,
+            None => return,
+        };
+        state.metrics.record_response(sent.status, sent.bytes);
+        if let Some(access_log) = &state.access_log {
+            let client_address = client_address(stream, &request, config.trust_proxy)
+                .unwrap_or_else(|| "-".to_string());
+            if let Err(err) = access_log.record(&client_address, &request, sent.status, sent.bytes)
+            {
+                log(
+                    &format!("Could not write to the access log: {}", err),
+                    LogLevel::ServerError,
+                );
+            }
+        }
+        if !sent.keep_alive {
+            return;
+        }
+        idle_timeout = config.keep_alive_timeout;
+    }
+}
+
+/// Returns the IP address of the client at the other end of the connection.
+/// Behind a trusted proxy, the first address in the `X-Forwarded-For` header is used instead,
+/// if the proxy sent one. Returns `None` if the address could not be determined.
+///
+/// # Arguments
+/// * `stream` - The client connection
+/// * `request` - The client's request
+/// * `trust_proxy` - Whether the client is a proxy whose headers can be trusted
+fn client_address(stream: &dyn Connection, request: &Request, trust_proxy: bool) -> Option<String> {
+    let forwarded = request
+        .get_header("X-Forwarded-For")
+        .filter(|_| trust_proxy)
+        .and_then(|value| value.split(',').next())
+        .map(str::trim)
+        .filter(|address| !address.is_empty());
+    match forwarded {
+        Some(address) => Some(address.to_string()),
+        None => stream
+            .socket()
+            .peer_addr()
+            .ok()
+            .map(|addr| addr.ip().to_string()),
+    }
+}
+
+/// Sends a redirect with the given status to the location, without a body.
+fn redirect(
+    stream: &mut dyn Connection,
+    exchange: Exchange,
+    status: HttpStatus,
+    location: String,
+) -> Option<Sent> {
+    let state = exchange.state;
+    let mut response = build_response(state, Vec::new(), None, status, false, exchange.keep_alive);
+    response.set_header("Location", location);
+    send_response(stream, exchange, &mut response)
+}
+
+/// Returns the path without the URL prefix, or `None` if the path isn't under the prefix.
+/// The prefix itself is the empty path, which is redirected to the prefixed root.
+///
+/// # Arguments
+/// * `path` - The requested path
+/// * `prefix` - The URL prefix, starting with a slash and without a trailing one
+fn strip_url_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(prefix)?;
+    if rest.is_empty() || rest.starts_with('/') {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+/// Returns the methods the server responds to, as listed in the `Allow` header.
+fn allowed_methods(config: &Arguments) -> &'static str {
+    if config.upload_dir.is_some() {
+        UPLOAD_METHODS
+    } else {
+        ALLOWED_METHODS
+    }
+}
+
+/// Writes an uploaded file to the upload folder, at the request's path.
+/// Responds with `201 Created` for a new file and `204 No Content` for a replaced one.
+/// Returns `None` if the body could not be received or the response could not be sent.
+///
+/// # Arguments
+/// * `stream` - The client connection
+/// * `request` - The upload request
+/// * `request_path` - The request's path without the URL prefix
+/// * `upload_dir` - The folder uploads are written to
+/// * `body` - The unread body of the request
+/// * `keep_alive` - Whether the connection may stay open after the response
+fn upload(
+    stream: &mut dyn Connection,
+    exchange: Exchange,
+    request_path: &str,
+    upload_dir: &str,
+    body: PendingBody,
+) -> Option<Sent> {
+    let request = exchange.request;
+    // Without a length, the end of the body can't be found
+    if request.get_header("Transfer-Encoding").is_some() {
+        log(
+            "Client sent an upload without a Content-Length",
+            LogLevel::ClientError,
+        );
+        return status_response(stream, exchange, HttpStatus::BadRequest);
+    }
+    let path = match upload_path(upload_dir, request_path) {
+        Some(path) => path,
+        None => {
+            log(
+                "Client tried to upload to a path outside the upload folder",
+                LogLevel::ClientError,
+            );
+            return status_response(stream, exchange, HttpStatus::BadRequest);
+        }
+    };
+    // The body goes to a temporary file first, so that an aborted upload leaves a file
+    // being replaced untouched
+    let (temporary, mut file) = match create_temporary_file(&path) {
+        Ok(created) => created,
+        Err(err) => {
+            log(
+                &format!("Could not create uploaded file {}: {}", path, err),
+                LogLevel::ServerError,
+            );
+            return status_response(stream, exchange, HttpStatus::ServerError);
+        }
+    };
+    if body.awaits_continue {
+        let mut interim = Response::new(HttpStatus::Continue, Vec::new());
+        if interim.send(stream).is_err() {
+            drop(file);
+            remove_temporary_file(&temporary);
+            return None;
+        }
+    }
+    if let Err(err) = receive_body(stream, body.pending, body.length, &mut file) {
+        log(
+            &format!("Could not receive uploaded file {}: {}", path, err),
+            LogLevel::ClientError,
+        );
+        drop(file);
+        remove_temporary_file(&temporary);
+        return None;
+    }
+    drop(file);
+    let replaced = Path::new(&path).is_file();
+    if let Err(err) = fs::rename(&temporary, &path) {
+        log(
+            &format!("Could not store uploaded file {}: {}", path, err),
+            LogLevel::ServerError,
+        );
+        remove_temporary_file(&temporary);
+        return status_response(stream, exchange, HttpStatus::ServerError);
+    }
+    log(&format!("Received upload {}", path), LogLevel::Info);
+
+    let status = if replaced {
+        HttpStatus::NoContent
+    } else {
+        HttpStatus::Created
+    };
+    let state = exchange.state;
+    let mut response = build_response(state, Vec::new(), None, status, false, exchange.keep_alive);
+    if replaced {
+        // A 204 has no body, so it must not have a length either
+        response.remove_header("Content-Length");
+    } else {
+        response.set_header("Location", request.path.to_string());
+    }
+    send_response(stream, exchange, &mut response)
+}
+
+/// Creates a new file next to the given path for receiving an upload to it, with a name
+/// that no other upload uses. Returns the file's path along with the file.
+fn create_temporary_file(path: &str) -> io::Result<(String, File)> {
+    static NEXT_UPLOAD: AtomicU64 = AtomicU64::new(0);
+    let target = Path::new(path);
+    let name = target
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    let temporary = target.with_file_name(format!(
+        ".{}.{}-{}.upload",
+        name,
+        std::process::id(),
+        NEXT_UPLOAD.fetch_add(1, Ordering::Relaxed)
+    ));
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temporary)?;
+    Ok((temporary.to_string_lossy().into_owned(), file))
+}
+
+/// Removes the temporary file of an upload that didn't complete.
+fn remove_temporary_file(path: &str) {
+    if let Err(err) = fs::remove_file(path) {
+        log(
+            &format!("Could not remove incomplete upload {}: {}", path, err),
+            LogLevel::ServerError,
+        );
+    }
+}
+
+/// Returns whether the client wants the connection kept open after the response.
+/// HTTP/1.1 connections persist unless the client asks to close them,
+/// HTTP/1.0 connections only if the client asks to keep them alive.
+fn keeps_alive(request: &Request) -> bool {
+    if request.protocol == "HTTP/1.0" {
+        request.has_connection_option("keep-alive")
+    } else {
+        !request.has_connection_option("close")
+    }
+}
+
+/// Responds to a single parsed request.
+/// Returns `None` if the response could not be sent.
+///
+/// # Arguments
+/// * `stream` - The client connection
+/// * `state` - State shared between all connections
+/// * `request` - The request to respond to
+/// * `body` - The request's body if it hasn't been read yet, for uploads.
+///   The connection is closed if the body is left unread
+/// * `keep_alive` - Whether the connection may stay open after the response
+fn handle_request(
+    stream: &mut dyn Connection,
+    state: &ServerState,
+    request: &Request,
+    body: Option<PendingBody>,
+    keep_alive: bool,
+) -> Option<Sent> {
+    let config = &state.config;
+    let client_address = client_address(stream, request, config.trust_proxy)
+        .unwrap_or_else(|| "Unknown".to_string());
+
+    let user_agent = request.get_header("User-Agent").unwrap_or("Unknown");
+
+    log_with(
+        "Request received:",
         LogLevel::Info,
+        &[
+            ("address", &client_address),
+            ("user-agent", user_agent),
+            ("method", request.method_str()),
+            ("path", request.path),
+            ("protocol", request.protocol),
+        ],
     );
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", config.port)).unwrap();
-    let pool = ThreadPool::new(config.threads);
 
-    let config = Arc::new(config);
+    let encoding = DYNAMIC_ENCODINGS
+        .iter()
+        .copied()
+        .find(|encoding| request.accepts_encoding(encoding));
 
-    for stream in listener.incoming() {
-        let config = Arc::clone(&config);
-        match stream {
-            Ok(stream) => {
-                pool.execute(move || {
-                    handle_connection(stream, config);
-                });
-            }
-            Err(err) => {
-                log(
-                    &format!(" Client failed to connect: {}", err),
-                    LogLevel::ClientError,
-                );
-            }
-        }
+    // Responses to HEAD requests never have a body
+    let send_body = request.method != Method::Head;
+    let exchange = Exchange {
+        state,
+        request,
+        include_body: send_body,
+        keep_alive,
+    };
+
+    if request.protocol != "HTTP/1.1" && request.protocol != "HTTP/1.0" {
+        return status_response(stream, exchange, HttpStatus::UnsupportedVersion);
+    }
+
+    // HTTP/1.1 requires the Host header, HTTP/1.0 predates it
+    let host = request.host_name();
+    if host.is_none() && request.protocol == "HTTP/1.1" {
+        log(
+            "Client sent a request without a Host header",
+            LogLevel::ClientError,
+        );
+        return status_response(stream, exchange, HttpStatus::BadRequest);
+    }
+    if !config.allowed_hosts.is_empty()
+        && !host.is_some_and(|host| {
+            config
+                .allowed_hosts
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(host))
+        })
+    {
+        log(
+            "Client requested a host that isn't served",
+            LogLevel::ClientError,
+        );
+        return status_response(stream, exchange, HttpStatus::MisdirectedRequest);
     }
-}
 
-fn handle_connection(mut stream: TcpStream, config: Arc<Arguments>) {
-    // Read max 1KB of request data
-    let mut buffer = [0; 1024];
+    // Behind a proxy, every served path starts with the prefix the server is deployed under
+    let request_path = match &config.url_prefix {
+        Some(prefix) if request.path != "*" => match strip_url_prefix(request.path, prefix) {
+            Some(path) => path,
+            None => {
+                // An unread upload body would be taken for the next request
+                let exchange = Exchange {
+                    keep_alive: keep_alive && body.is_none(),
+                    ..exchange
+                };
+                return not_found(stream, exchange, encoding);
+            }
+        },
+        _ => request.path,
+    };
 
-    if stream.read(&mut buffer).is_err() {
+    // Preflight requests never carry credentials, so they are answered without them
+    let authorized = state
+        .auth
+        .as_ref()
+        .is_none_or(|auth| auth.authorizes(request.get_header("Authorization")));
+    if !authorized && request.method != Method::Options {
         log(
-            &format!("Client sent malformed stream"),
+            "Client sent a request without valid credentials",
             LogLevel::ClientError,
         );
-        empty_response(&mut stream, HttpStatus::BadRequest);
-        return;
+        let status = HttpStatus::Unauthorized;
+        let (bytes, headers) = negotiated_status_page(&status, request);
+        // An unread upload body would be taken for the next request
+        let keep_alive = keep_alive && body.is_none();
+        let mut response =
+            build_response(state, bytes, Some(headers), status, send_body, keep_alive);
+        response.set_header(
+            "WWW-Authenticate",
+            format!("Basic realm=\"{}\", charset=\"UTF-8\"", AUTH_REALM),
+        );
+        return send_response(stream, exchange, &mut response);
+    }
+
+    if let Some(response) = config
+        .handler
+        .as_ref()
+        .and_then(|handler| handler.handle(request))
+    {
+        // An unread upload body would be taken for the next request
+        let exchange = Exchange {
+            keep_alive: keep_alive && body.is_none(),
+            ..exchange
+        };
+        return send_handler_response(stream, exchange, response);
     }
 
-    let serve_path_str = &config.directory;
+    match request.method {
+        Method::Options => return options_response(stream, exchange),
+        // The asterisk refers to the whole server, which only makes sense for OPTIONS
+        _ if request.path == "*" => {
+            return status_response(stream, exchange, HttpStatus::BadRequest)
+        }
+        Method::Get | Method::Head => {}
+        Method::Put | Method::Post if body.is_some() => {
+            return match (&config.upload_dir, body) {
+                (Some(upload_dir), Some(body)) => {
+                    upload(stream, exchange, request_path, upload_dir, body)
+                }
+                _ => server_error(stream, exchange.closing()),
+            };
+        }
+        _ => {
+            let (bytes, headers) = negotiated_status_page(&HttpStatus::NotAllowed, request);
+            let mut response = build_response(
+                state,
+                bytes,
+                Some(headers),
+                HttpStatus::NotAllowed,
+                true,
+                keep_alive,
+            );
+            response.set_header("Allow", String::from(allowed_methods(config)));
+            return send_response(stream, exchange, &mut response);
+        }
+    }
+
+    // Built-in endpoints take precedence over files with the same path
+    if config.health_path.as_deref() == Some(request_path) {
+        return plain_text(stream, exchange, "OK\n");
+    }
+    if config.metrics_path.as_deref() == Some(request_path) {
+        return plain_text(stream, exchange, &state.metrics.render());
+    }
+
+    let rule = match state.redirects.read() {
+        Ok(redirects) => redirects
+            .as_ref()
+            .and_then(|rules| rules.get(request_path).cloned()),
+        Err(_) => None,
+    };
+    if let Some(rule) = rule {
+        let status = if rule.permanent {
+            HttpStatus::MovedPermanently
+        } else {
+            HttpStatus::Found
+        };
+        // Paths on this server are under the prefix too, other sites' addresses are not
+        let target = match &config.url_prefix {
+            Some(prefix) if rule.target.starts_with('/') && !rule.target.starts_with("//") => {
+                format!("{}{}", prefix, rule.target)
+            }
+            _ => rule.target.clone(),
+        };
+        // The query is passed on unless the target has its own
+        let location = match request.query {
+            Some(query) if !target.contains('?') => format!("{}?{}", target, query),
+            _ => target,
+        };
+        return redirect(stream, exchange, status, location);
+    }
+
+    let range = match request.method {
+        Method::Get => request.byte_range(),
+        _ => None,
+    };
+
+    // Only the file itself is served, not the directory it happens to be in
+    if let Some(name) = &state.single_file {
+        let path = decode(request_path.trim_start_matches('/')).ok();
+        if !request_path.is_empty() && request_path != "/" && path.as_deref() != Some(name) {
+            return not_found(stream, exchange, encoding);
+        }
+    }
+
+    // Relative links on a directory's page only resolve inside it with a trailing slash
+    if !request_path.ends_with('/')
+        && config
+            .directories
+            .iter()
+            .any(|directory| is_directory(directory, request_path, config.follow_symlinks))
+    {
+        let location = match request.query {
+            Some(query) => format!("{}/?{}", request.path, query),
+            None => format!("{}/", request.path),
+        };
+        return redirect(stream, exchange, HttpStatus::MovedPermanently, location);
+    }
+
+    // The directory's own URL is the canonical one for its index page
+    if config.canonical_index && is_directory_index(config, request_path) {
+        let directory = &request.path[..=request.path.rfind('/').unwrap_or(0)];
+        let location = match request.query {
+            Some(query) => format!("{}?{}", directory, query),
+            None => directory.to_string(),
+        };
+        return redirect(stream, exchange, HttpStatus::MovedPermanently, location);
+    }
 
-    let buffer_str = String::from_utf8_lossy(&buffer);
-    let request = match Request::parse(&buffer_str) {
-        Some(x) => x,
+    let (serve_path_str, content) = match find_content(config, request_path, &config.index_files) {
+        Some(found) => found,
         None => {
-            empty_response(&mut stream, HttpStatus::BadRequest);
-            return;
+            if config.autoindex {
+                let template = match state.listing_template.read() {
+                    Ok(template) => template.clone(),
+                    Err(_) => Cow::Borrowed(PLAIN_TEMPLATE),
+                };
+                let page = config.directories.iter().find_map(|directory| {
+                    directory_listing(
+                        directory,
+                        request_path,
+                        config.url_prefix.as_deref().unwrap_or(""),
+                        config.follow_symlinks,
+                        &template,
+                    )
+                });
+                if let Some(page) = page {
+                    let content_headers = ContentHeaders {
+                        content_type: Cow::Borrowed("text/html"),
+                        charset: Some(String::from("UTF-8")),
+                        cache_age: 0,
+                        compress: true,
+                        etag: None,
+                        last_modified: None,
+                        accept_ranges: false,
+                        download_name: None,
+                    };
+                    let mut response = encoded_response(
+                        exchange,
+                        page.into_bytes(),
+                        Some(content_headers),
+                        encoding,
+                        HttpStatus::Ok,
+                    );
+                    // Generated pages are sent in chunks to clients that understand them
+                    if request.protocol == "HTTP/1.1" {
+                        response.set_chunked();
+                    }
+                    return send_response(stream, exchange, &mut response);
+                }
+            }
+            // Browsers request the icon on every page load, which would otherwise end in a 404
+            if config.favicon && request_path == "/favicon.ico" {
+                let content_headers = ContentHeaders {
+                    content_type: Cow::Borrowed("image/x-icon"),
+                    charset: None,
+                    cache_age: FAVICON_CACHE_AGE,
+                    compress: false,
+                    etag: None,
+                    last_modified: None,
+                    accept_ranges: false,
+                    download_name: None,
+                };
+                return success(
+                    stream,
+                    exchange,
+                    DEFAULT_FAVICON.to_vec(),
+                    content_headers,
+                    None,
+                );
+            }
+            match spa_fallback(config, request_path) {
+                Some(found) => found,
+                None => return not_found(stream, exchange, encoding),
+            }
         }
     };
-    let client_address = match stream.local_addr() {
-        Ok(addr) => addr.ip().to_string(),
-        Err(_) => "Unknown".to_string(),
+
+    let content_headers = state.content_headers(&content);
+    if is_not_modified(request, &content_headers) {
+        return not_modified(stream, exchange, content_headers);
+    }
+
+    // A range of a changed resource would be mixed with the old one, so it's sent whole
+    let range = range.filter(|_| {
+        request.if_range_matches(
+            content_headers.etag.as_deref(),
+            content_headers.last_modified,
+        )
+    });
+    if let Some(range) = &range {
+        return partial_content(stream, exchange, &content, range);
+    }
+
+    if content_headers.compress {
+        for (encoding, extension) in PRECOMPRESSED_EXTENSIONS {
+            if !request.accepts_encoding(encoding) {
+                continue;
+            }
+            if let Some(compressed) =
+                content.precompressed(serve_path_str, extension, config.follow_symlinks)
+            {
+                let response =
+                    precompressed_response(exchange, &compressed, content_headers, encoding);
+                return match response {
+                    Ok(mut response) => send_response(stream, exchange, &mut response),
+                    Err(_) => server_error(stream, exchange),
+                };
+            }
+        }
+    }
+
+    let size = match content.size() {
+        Ok(size) if size > STREAM_THRESHOLD => {
+            return match file_response(exchange, &content, HttpStatus::Ok, 0, size) {
+                Ok(mut response) => send_response(stream, exchange, &mut response),
+                Err(_) => server_error(stream, exchange),
+            };
+        }
+        Ok(size) => size,
+        Err(_) => return server_error(stream, exchange),
     };
 
-    let user_agent = request.headers.get("User-Agent").unwrap_or(&"Unknown");
+    let compressible = content_headers.compress && size >= config.min_compress_size as u64;
+    let bytes = match (&state.file_cache, encoding) {
+        // The cache keeps the compressed bytes too, so the file is only compressed once
+        (Some(cache), Some(encoding)) if compressible => {
+            return match cache.get_compressed(&content, encoding, config.compression_level) {
+                Ok(bytes) => compressed(stream, exchange, bytes, content_headers, encoding),
+                Err(_) => server_error(stream, exchange),
+            };
+        }
+        (Some(cache), _) => cache.get_bytes(&content),
+        (None, _) => content.get_bytes(),
+    };
+    match bytes {
+        Ok(bytes) => success(stream, exchange, bytes, content_headers, encoding),
+        Err(_) => server_error(stream, exchange),
+    }
+}
 
-    log(
-        &format!(
-            "Request received:\naddress: {}\nuser-agent: {}\nmethod: {}\npath: {}\nprotocol: {}",
-            client_address, user_agent, request.method, request.path, request.protocol
-        ),
-        LogLevel::Info,
+/// Sends content that has already been compressed with the given content encoding.
+fn compressed(
+    stream: &mut dyn Connection,
+    exchange: Exchange,
+    bytes: Vec<u8>,
+    content_headers: ContentHeaders,
+    encoding: &str,
+) -> Option<Sent> {
+    let mut headers = content_headers;
+    headers.compress = false;
+    let mut response = build_response(
+        exchange.state,
+        bytes,
+        Some(headers),
+        HttpStatus::Ok,
+        exchange.include_body,
+        exchange.keep_alive,
     );
+    response.set_content_encoding(encoding);
+    send_response(stream, exchange, &mut response)
+}
 
-    let accepts_gzip = request
-        .headers
-        .get("Accept-Encoding")
-        .and_then(|encodings| {
-            for substr in encodings.split(',') {
-                if substr.trim_start().starts_with("gzip") {
-                    return Some(());
-                }
+/// Returns whether the client's cached copy of the content is still valid,
+/// according to the request's conditional headers.
+fn is_not_modified(request: &Request, content_headers: &ContentHeaders) -> bool {
+    // If-Modified-Since is only considered when If-None-Match is absent
+    if request.get_header("If-None-Match").is_some() {
+        return match &content_headers.etag {
+            Some(etag) => request.if_none_match(etag) == Some(true),
+            None => false,
+        };
+    }
+    match (request.if_modified_since(), content_headers.last_modified) {
+        // A date in the future can't be trusted, so the content is sent anyway
+        (Some(since), Some(modified)) if since <= SystemTime::now() => {
+            // Header dates only have a resolution of one second
+            let since = since.duration_since(UNIX_EPOCH).map(|d| d.as_secs());
+            let modified = modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs());
+            matches!((since, modified), (Ok(since), Ok(modified)) if modified <= since)
+        }
+        _ => false,
+    }
+}
+
+/// Outcome of reading a request's headers from a connection
+enum HeaderRead {
+    /// The complete header block was read, including the terminating empty line
+    Complete,
+    /// The client closed the connection or went idle before sending a new request
+    Closed,
+    /// The client stopped sending in the middle of a request
+    TimedOut,
+    /// The headers could not be read, were cut short, or exceeded the size limit
+    Invalid,
+}
+
+/// Reads from the stream until the end of the request headers, which are copied to `headers`.
+/// Any bytes read past the headers are left in `pending` for the next read.
+///
+/// # Arguments
+/// * `stream` - The client connection
+/// * `pending` - Bytes already received but not yet consumed
+/// * `headers` - Buffer for the header block, replacing its previous contents
+/// * `max_size` - The maximum allowed size of the header block in bytes
+/// * `idle_timeout` - How long to wait for the request to start
+/// * `timeout` - How long to wait for each read once the request has started
+fn read_headers(
+    stream: &mut dyn Connection,
+    pending: &mut Vec<u8>,
+    headers: &mut Vec<u8>,
+    max_size: usize,
+    idle_timeout: Duration,
+    timeout: Duration,
+) -> HeaderRead {
+    let mut chunk = [0; 1024];
+
+    let initial_timeout = if pending.is_empty() {
+        idle_timeout
+    } else {
+        timeout
+    };
+    if stream
+        .socket()
+        .set_read_timeout(Some(initial_timeout))
+        .is_err()
+    {
+        return HeaderRead::Invalid;
+    }
+
+    loop {
+        if let Some(end) = header_end(pending) {
+            if end > max_size {
+                return HeaderRead::Invalid;
             }
-            None
-        })
-        .is_some();
+            // Both buffers keep their capacity for the next request
+            headers.clear();
+            headers.extend_from_slice(&pending[..end]);
+            pending.drain(..end);
+            return HeaderRead::Complete;
+        }
+        if pending.len() > max_size {
+            return HeaderRead::Invalid;
+        }
 
-    if request.protocol != "HTTP/1.1" {
-        empty_response(&mut stream, HttpStatus::UnsupportedVersion);
-        return;
+        let count = match stream.read(&mut chunk) {
+            Ok(count) => count,
+            Err(_) if pending.is_empty() => return HeaderRead::Closed,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                return HeaderRead::TimedOut
+            }
+            Err(_) => return HeaderRead::Invalid,
+        };
+        if count == 0 {
+            return if pending.is_empty() {
+                HeaderRead::Closed
+            } else {
+                HeaderRead::Invalid
+            };
+        }
+        if pending.is_empty() && stream.socket().set_read_timeout(Some(timeout)).is_err() {
+            return HeaderRead::Invalid;
+        }
+        pending.extend_from_slice(&chunk[..count]);
+    }
+}
+
+/// Returns the length of the header block at the start of the bytes, up to and including
+/// the empty line that ends it. Empty lines before the request line belong to the block,
+/// as do the line ends, which may be `\r\n` or a lone `\n`.
+/// Returns `None` if the block hasn't ended yet.
+fn header_end(bytes: &[u8]) -> Option<usize> {
+    let mut line_start = 0;
+    let mut started = false;
+    for (i, byte) in bytes.iter().enumerate() {
+        if *byte != b'\n' {
+            continue;
+        }
+        let line = &bytes[line_start..i];
+        let empty = line.is_empty() || line == b"\r";
+        if empty && started {
+            return Some(i + 1);
+        }
+        started |= !empty;
+        line_start = i + 1;
+    }
+    None
+}
+
+/// A request body that hasn't been read from the connection yet
+///
+/// * `pending` - Bytes already received, starting with the body
+/// * `length` - The size of the body in bytes
+/// * `awaits_continue` - Whether the client waits for a `100 Continue` before sending the body
+struct PendingBody<'a> {
+    pending: &'a mut Vec<u8>,
+    length: u64,
+    awaits_continue: bool,
+}
+
+/// Consumes a request body of `length` bytes, starting with any bytes in `pending`.
+fn discard_body(stream: &mut dyn Connection, pending: &mut Vec<u8>, length: u64) -> io::Result<()> {
+    receive_body(stream, pending, length, &mut io::sink())
+}
+
+/// Copies a request body of `length` bytes to `writer`, starting with any bytes in `pending`.
+/// Returns `io::Error` if the body could not be read or written, or was cut short.
+fn receive_body<W: Write>(
+    stream: &mut dyn Connection,
+    pending: &mut Vec<u8>,
+    length: u64,
+    writer: &mut W,
+) -> io::Result<()> {
+    let buffered = pending.len().min(length as usize);
+    writer.write_all(&pending[..buffered])?;
+    pending.drain(..buffered);
+
+    let remaining = length - buffered as u64;
+    let copied = io::copy(&mut stream.take(remaining), writer)?;
+    if copied < remaining {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(())
+}
+
+fn server_error(stream: &mut dyn Connection, exchange: Exchange) -> Option<Sent> {
+    error_response(stream, exchange, HttpStatus::ServerError, None)
+}
+
+/// Sends an error response with the status's error page as the body.
+fn error_response(
+    stream: &mut dyn Connection,
+    exchange: Exchange,
+    status: HttpStatus,
+    encoding: Option<&str>,
+) -> Option<Sent> {
+    let (bytes, headers) = error_page(&status, exchange.state);
+    let mut response = encoded_response(exchange, bytes, Some(headers), encoding, status);
+    send_response(stream, exchange, &mut response)
+}
+
+/// Returns the content at the given path in the first served directory that contains it,
+/// along with that directory.
+///
+/// # Arguments
+/// * `config` - The server's configuration
+/// * `path` - Percent-encoded path of the requested file
+/// * `index_files` - File names served for a directory, tried in order
+fn find_content<'a>(
+    config: &'a Arguments,
+    path: &str,
+    index_files: &[String],
+) -> Option<(&'a str, HttpContent)> {
+    config.directories.iter().find_map(|directory| {
+        HttpContent::new(directory, path, index_files, config.follow_symlinks)
+            .map(|content| (directory.as_str(), content))
+    })
+}
+
+/// Returns whether the path names the file that is served as its directory's index page.
+/// An index file that is shadowed by an earlier one in `index_files` doesn't count.
+fn is_directory_index(config: &Arguments, path: &str) -> bool {
+    let (directory, name) = match path.rfind('/') {
+        Some(slash) => path.split_at(slash + 1),
+        None => return false,
+    };
+    let name = decode(name).unwrap_or_default();
+    if !config.index_files.iter().any(|index| *index == name) {
+        return false;
     }
+    match (
+        find_content(config, path, &[]),
+        find_content(config, directory, &config.index_files),
+    ) {
+        (Some((_, file)), Some((_, index))) => Path::new(file.path()) == Path::new(index.path()),
+        _ => false,
+    }
+}
+
+/// Returns the index page served for a missing path in single-page app mode, along with
+/// its directory. Paths with a file extension are treated as missing assets and get `None`,
+/// as does every path when the mode is disabled.
+fn spa_fallback<'a>(config: &'a Arguments, path: &str) -> Option<(&'a str, HttpContent)> {
+    if !config.spa || Path::new(path).extension().is_some() {
+        return None;
+    }
+    find_content(config, "/", &config.index_files)
+}
 
-    let send_body = if request.method != "HEAD" {
-        true
+/// Sends a `404` response for the requested path. Clients preferring JSON get a JSON
+/// description of the status. Otherwise the custom `404.html` page is used if a served
+/// folder contains one, or a short HTML note naming the missing path.
+fn not_found(
+    stream: &mut dyn Connection,
+    exchange: Exchange,
+    encoding: Option<&str>,
+) -> Option<Sent> {
+    let status = HttpStatus::NotFound;
+    let request_path = exchange.request.path;
+    let page = if exchange.request.prefers_json() {
+        Some(json_status_page(&status))
     } else {
-        false
+        custom_error_page(&status, exchange.state)
     };
+    let (bytes, headers) = page.unwrap_or_else(|| {
+        let path = decode(request_path).map_or(Cow::Borrowed(request_path), |path| path);
+        let page = format!(
+            "<!DOCTYPE html>
+<html lang=\"en\">
+  <head>
+    <meta charset=\"UTF-8\">
+    <title>404 Not Found</title>
+  </head>
 
-    if request.method != "GET" && request.method != "HEAD" {
-        empty_response(&mut stream, HttpStatus::NotAllowed);
-        return;
+  <body>
+    <h1>Not Found</h1>
+    <p>The requested path {} was not found on this server.</p>
+  </body>
+</html>
+",
+            escape_html(&path)
+        );
+        let headers = ContentHeaders {
+            content_type: Cow::Borrowed("text/html"),
+            charset: Some(String::from("UTF-8")),
+            cache_age: 0,
+            compress: true,
+            etag: None,
+            last_modified: None,
+            accept_ranges: false,
+            download_name: None,
+        };
+        (page.into_bytes(), headers)
+    });
+    let mut response = encoded_response(exchange, bytes, Some(headers), encoding, status);
+    send_response(stream, exchange, &mut response)
+}
+
+/// Returns the custom page for the given status and its content headers, if a served
+/// folder contains a page named after the status code, e.g. `404.html`.
+fn custom_error_page(
+    status: &HttpStatus,
+    state: &ServerState,
+) -> Option<(Vec<u8>, ContentHeaders<'static>)> {
+    let page_path = format!("{}.html", status.code());
+    let (_, content) = find_content(&state.config, &page_path, &[])?;
+    match content.get_bytes() {
+        Ok(bytes) => {
+            let mut headers = state.content_headers(&content);
+            // Validators describe the page file, not the resource that caused the error
+            headers.etag = None;
+            headers.last_modified = None;
+            headers.accept_ranges = false;
+            headers.download_name = None;
+            Some((bytes, headers))
+        }
+        Err(err) => {
+            log(
+                &format!("Could not read error page {}: {}", page_path, err),
+                LogLevel::ServerError,
+            );
+            None
+        }
     }
+}
 
-    match HttpContent::new(serve_path_str, request.path) {
-        Some(content) => match content.get_bytes() {
-            Ok(bytes) => success(
-                &mut stream,
-                &bytes,
-                content.content_headers(),
-                accepts_gzip,
-                send_body,
-            ),
-            Err(_) => server_error(&mut stream, send_body),
-        },
-        None => match HttpContent::new(serve_path_str, "404.html") {
-            Some(content) => match content.get_bytes() {
-                Ok(bytes) => not_found(
-                    &mut stream,
-                    &bytes,
-                    content.content_headers(),
-                    accepts_gzip,
-                    send_body,
-                ),
-                Err(_) => server_error(&mut stream, send_body),
-            },
-            None => server_error(&mut stream, send_body),
-        },
+/// Returns the error page for the given status and its content headers.
+/// A custom page named after the status code, e.g. `404.html`, is used if a served
+/// folder contains one. Otherwise the page is a plain-text description of the status.
+fn error_page(status: &HttpStatus, state: &ServerState) -> (Vec<u8>, ContentHeaders<'static>) {
+    custom_error_page(status, state).unwrap_or_else(|| status_page(status))
+}
+
+/// Returns a plain-text page describing the status, e.g. `404 Not Found`,
+/// and its content headers.
+fn status_page(status: &HttpStatus) -> (Vec<u8>, ContentHeaders<'static>) {
+    let text = format!("{} {}", status.code(), status.reason());
+    let headers = ContentHeaders {
+        content_type: Cow::Borrowed("text/plain"),
+        charset: Some(String::from("UTF-8")),
+        cache_age: 0,
+        compress: false,
+        etag: None,
+        last_modified: None,
+        accept_ranges: false,
+        download_name: None,
+    };
+    (text.into_bytes(), headers)
+}
+
+/// Returns a JSON description of the status, e.g. `{"error":"Not Found","status":404}`,
+/// and its content headers.
+fn json_status_page(status: &HttpStatus) -> (Vec<u8>, ContentHeaders<'static>) {
+    let json = format!(
+        "{{\"error\":\"{}\",\"status\":{}}}",
+        status.reason(),
+        status.code()
+    );
+    let headers = ContentHeaders {
+        content_type: Cow::Borrowed("application/json"),
+        charset: None,
+        cache_age: 0,
+        compress: false,
+        etag: None,
+        last_modified: None,
+        accept_ranges: false,
+        download_name: None,
+    };
+    (json.into_bytes(), headers)
+}
+
+/// Returns the status page in the format the client prefers, JSON or plain text.
+fn negotiated_status_page(
+    status: &HttpStatus,
+    request: &Request,
+) -> (Vec<u8>, ContentHeaders<'static>) {
+    if request.prefers_json() {
+        json_status_page(status)
+    } else {
+        status_page(status)
+    }
+}
+
+/// Tells the client that its cached copy of the content is still valid.
+fn not_modified(
+    stream: &mut dyn Connection,
+    exchange: Exchange,
+    content_headers: ContentHeaders,
+) -> Option<Sent> {
+    // The headers are those of the content, including its `Vary`, as required by RFC 7232
+    let mut response = build_response(
+        exchange.state,
+        Vec::new(),
+        Some(content_headers),
+        HttpStatus::NotModified,
+        false,
+        exchange.keep_alive,
+    );
+    // A 304 has no body, and its length would otherwise be mistaken for the content's
+    response.remove_header("Content-Length");
+    send_response(stream, exchange, &mut response)
+}
+
+/// Answers an `OPTIONS` request with the allowed methods, either for a single path
+/// or for the whole server with `OPTIONS *`.
+/// A CORS preflight request is answered with the allowed methods and headers,
+/// if cross-origin requests are enabled.
+fn options_response(stream: &mut dyn Connection, exchange: Exchange) -> Option<Sent> {
+    let (state, request) = (exchange.state, exchange.request);
+    let config = &state.config;
+    let mut response = build_response(
+        state,
+        Vec::new(),
+        None,
+        HttpStatus::NoContent,
+        false,
+        exchange.keep_alive,
+    );
+    // A 204 has no body, so it must not have a length either
+    response.remove_header("Content-Length");
+    response.set_header("Allow", String::from(allowed_methods(config)));
+    let preflight = request.get_header("Origin").is_some()
+        && request
+            .get_header("Access-Control-Request-Method")
+            .is_some();
+    if config.cors_origin.is_some() && preflight {
+        response.set_header(
+            "Access-Control-Allow-Methods",
+            String::from(allowed_methods(config)),
+        );
+        if let Some(headers) = request.get_header("Access-Control-Request-Headers") {
+            response.set_header("Access-Control-Allow-Headers", headers.to_string());
+        }
+        response.set_header("Access-Control-Max-Age", CORS_MAX_AGE.to_string());
     }
+    send_response(stream, exchange, &mut response)
 }
 
-fn server_error(stream: &mut TcpStream, include_body: bool) {
+/// Sends a generated plain-text page that must not be cached.
+fn plain_text(stream: &mut dyn Connection, exchange: Exchange, text: &str) -> Option<Sent> {
     let content_headers = ContentHeaders {
-        content_type: "text/plain",
+        content_type: Cow::Borrowed("text/plain"),
+        charset: Some(String::from("UTF-8")),
         cache_age: 0,
         compress: false,
+        etag: None,
+        last_modified: None,
+        accept_ranges: false,
+        download_name: None,
     };
-    respond(
+    success(
         stream,
-        b"500 Internal Server Error",
-        Some(content_headers),
-        HttpStatus::ServerError,
-        include_body,
-    );
+        exchange,
+        text.as_bytes().to_vec(),
+        content_headers,
+        None,
+    )
+}
+
+/// Sends a response with a plain-text description of the status, and closes the connection.
+fn status_response(
+    stream: &mut dyn Connection,
+    exchange: Exchange,
+    status: HttpStatus,
+) -> Option<Sent> {
+    let exchange = exchange.closing();
+    let (bytes, headers) = negotiated_status_page(&status, exchange.request);
+    let mut response = encoded_response(exchange, bytes, Some(headers), None, status);
+    send_response(stream, exchange, &mut response)
+}
+
+/// Sends an error response to a client whose request can't be handled,
+/// and counts it in the server's metrics.
+/// A `503` tells the client how long to wait before retrying.
+fn reject(stream: &mut dyn Connection, state: &ServerState, status: HttpStatus) {
+    let unavailable = matches!(status, HttpStatus::ServiceUnavailable);
+    let (bytes, headers) = status_page(&status);
+    let mut response = build_response(state, bytes, Some(headers), status, true, false);
+    if unavailable {
+        let seconds = state.config.retry_after.as_secs();
+        response.set_header("Retry-After", seconds.to_string());
+    }
+    if let Some(sent) = write_response(stream, &mut response) {
+        state.metrics.record_response(sent.status, sent.bytes);
+    }
 }
 
-fn empty_response(stream: &mut TcpStream, status: HttpStatus) {
-    respond(stream, b"", None, status, false);
+/// Turns away a connection the server has no room for with a `503`.
+/// Over TLS the response could only be sent after a handshake, which would hold up
+/// accepting other connections, so the connection is closed without one.
+fn turn_away(stream: &mut TcpStream, state: &ServerState) {
+    if state.tls.is_none() {
+        reject(stream, state, HttpStatus::ServiceUnavailable);
+    }
 }
 
 fn success(
-    stream: &mut TcpStream,
-    bytebuffer: &[u8],
+    stream: &mut dyn Connection,
+    exchange: Exchange,
+    bytebuffer: Vec<u8>,
     content_headers: ContentHeaders,
-    allow_compression: bool,
-    include_body: bool,
-) {
-    let mut headers = ContentHeaders { ..content_headers };
-    headers.compress = content_headers.compress && allow_compression;
-    respond(
-        stream,
+    encoding: Option<&str>,
+) -> Option<Sent> {
+    let mut response = encoded_response(
+        exchange,
         bytebuffer,
-        Some(headers),
+        Some(content_headers),
+        encoding,
         HttpStatus::Ok,
-        include_body,
     );
+    send_response(stream, exchange, &mut response)
 }
 
-fn not_found(
-    stream: &mut TcpStream,
-    bytebuffer: &[u8],
+/// Sends the requested byte range of a file, or a 416 response if the range
+/// lies outside the file. Partial responses are never compressed.
+/// Returns `None` if the response could not be sent.
+fn partial_content(
+    stream: &mut dyn Connection,
+    exchange: Exchange,
+    content: &HttpContent,
+    range: &ByteRange,
+) -> Option<Sent> {
+    let total = match content.size() {
+        Ok(size) => size,
+        Err(_) => return server_error(stream, exchange),
+    };
+
+    let bounds = range.resolve(total);
+    let mut response = match bounds {
+        Some((start, end)) => match file_response(
+            exchange,
+            content,
+            HttpStatus::PartialContent,
+            start,
+            end - start + 1,
+        ) {
+            Ok(response) => response,
+            Err(_) => return server_error(stream, exchange),
+        },
+        None => build_response(
+            exchange.state,
+            Vec::new(),
+            None,
+            HttpStatus::RangeNotSatisfiable,
+            false,
+            exchange.keep_alive,
+        ),
+    };
+    response.set_content_range(bounds, total);
+    send_response(stream, exchange, &mut response)
+}
+
+/// Returns a response that streams a precompressed version of a file, with the original
+/// file's content headers.
+/// Returns `io::Error` if the file could not be opened.
+fn precompressed_response<'a>(
+    exchange: Exchange,
+    compressed: &HttpContent,
     content_headers: ContentHeaders,
-    allow_compression: bool,
-    include_body: bool,
-) {
+    encoding: &str,
+) -> io::Result<Response<'a>> {
     let mut headers = ContentHeaders { ..content_headers };
-    headers.compress = content_headers.compress && allow_compression;
-    respond(
-        stream,
-        bytebuffer,
+    headers.compress = false;
+
+    let mut response = build_response(
+        exchange.state,
+        Vec::new(),
         Some(headers),
-        HttpStatus::NotFound,
-        include_body,
+        HttpStatus::Ok,
+        exchange.include_body,
+        exchange.keep_alive,
+    );
+    let size = compressed.size()?;
+    response.set_file_body(compressed.open_range(0, size)?);
+    response.set_content_encoding(encoding);
+    Ok(response)
+}
+
+/// Returns an uncompressed response that streams `length` bytes of the file,
+/// starting at offset `start`.
+/// Returns `io::Error` if the file could not be opened.
+fn file_response<'a>(
+    exchange: Exchange,
+    content: &HttpContent,
+    status: HttpStatus,
+    start: u64,
+    length: u64,
+) -> io::Result<Response<'a>> {
+    let mut content_headers = exchange.state.content_headers(content);
+    content_headers.compress = false;
+
+    let mut response = build_response(
+        exchange.state,
+        Vec::new(),
+        Some(content_headers),
+        status,
+        exchange.include_body,
+        exchange.keep_alive,
     );
+    response.set_file_body(content.open_range(start, length)?);
+    Ok(response)
 }
 
-fn respond(
-    stream: &mut TcpStream,
-    bytebuffer: &[u8],
+/// Returns a response with the default and content specific headers set, and the payload
+/// compressed with the given content encoding if the content is worth compressing.
+fn encoded_response<'a>(
+    exchange: Exchange,
+    bytebuffer: Vec<u8>,
+    content_headers: Option<ContentHeaders>,
+    encoding: Option<&str>,
+    status: HttpStatus,
+) -> Response<'a> {
+    let config = &exchange.state.config;
+    let compress = content_headers
+        .as_ref()
+        .is_some_and(|headers| headers.compress)
+        && bytebuffer.len() >= config.min_compress_size;
+    let mut response = build_response(
+        exchange.state,
+        bytebuffer,
+        content_headers,
+        status,
+        exchange.include_body,
+        exchange.keep_alive,
+    );
+    if let Some(encoding) = encoding.filter(|_| compress) {
+        let compressed = match encoding {
+            "br" => response.compress_brotli(config.compression_level),
+            "deflate" => response.compress_deflate(config.compression_level),
+            _ => response.compress_gzip(config.compression_level),
+        };
+        if compressed.is_err() {
+            log("Could not compress file", LogLevel::ServerError);
+        }
+    }
+    response
+}
+
+/// Returns a response with the default and content specific headers set, according to the
+/// server's configuration.
+fn build_response<'a>(
+    state: &ServerState,
+    bytes: Vec<u8>,
     content_headers: Option<ContentHeaders>,
     status: HttpStatus,
     include_body: bool,
-) {
-    let bytes = bytebuffer.to_vec();
+    keep_alive: bool,
+) -> Response<'a> {
     let mut response = Response::new(status, bytes);
+    response.suppress_body = !include_body;
 
-    response.set_default_headers();
+    response.set_default_headers(&state.response_settings);
+    if let Some(id) = current_request_id() {
+        response.set_header("X-Request-Id", id);
+    }
+    if keep_alive {
+        let remaining = REMAINING_REQUESTS.with(Cell::get);
+        let timeout = state.config.keep_alive_timeout.as_secs();
+        response.set_keep_alive(timeout, remaining);
+    }
     if let Some(headers) = content_headers {
-        response.set_content_headers(&headers);
-        if headers.compress && response.compress_gzip().is_err() {
-            log("Could not compress file", LogLevel::ServerError);
+        response.set_content_headers(&headers, state.response_settings.cache_policy);
+    }
+    response
+}
+
+/// Writes a response returned by the custom `Handler` to the client, with the default
+/// headers added unless the handler set them itself.
+/// Returns `None` if the response could not be sent.
+fn send_handler_response(
+    stream: &mut dyn Connection,
+    exchange: Exchange,
+    custom: Response<'static>,
+) -> Option<Sent> {
+    let mut response = build_response(
+        exchange.state,
+        custom.payload,
+        None,
+        custom.status,
+        exchange.include_body,
+        exchange.keep_alive,
+    );
+    for (name, value) in custom.headers {
+        response.set_header(name, value);
+    }
+    response.file_body = custom.file_body;
+    if custom.chunked {
+        response.set_chunked();
+    }
+    send_response(stream, exchange, &mut response)
+}
+
+/// Writes a response to the client, after the configured response transformers
+/// have been applied to it. Returns `None` if the response could not be sent.
+fn send_response(
+    stream: &mut dyn Connection,
+    exchange: Exchange,
+    response: &mut Response,
+) -> Option<Sent> {
+    for transformer in &exchange.state.config.transformers {
+        transformer(exchange.request, response);
+    }
+    write_response(stream, response)
+}
+
+/// Writes a response to the client as it is.
+/// Returns `None` if the response could not be sent.
+fn write_response(stream: &mut dyn Connection, response: &mut Response) -> Option<Sent> {
+    match response.send(stream) {
+        Ok(bytes) => Some(Sent {
+            status: response.status.code(),
+            bytes,
+            keep_alive: response.header("Connection") == Some("keep-alive"),
+        }),
+        Err(e) => {
+            log(
+                &format!("Could not send a response: {}", e),
+                send_error_level(&e),
+            );
+            None
         }
     }
+}
+
+/// Returns the level to log an error sending a response at.
+/// Clients hanging up mid-response is normal, not a fault of the server.
+fn send_error_level(err: &io::Error) -> LogLevel {
+    match err.kind() {
+        io::ErrorKind::BrokenPipe
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted => LogLevel::ClientError,
+        _ => LogLevel::ServerError,
+    }
+}
 
-    let result = response.send(stream, include_body);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if let Err(e) = result {
-        log(
-            &format!("Could not send a response: {}", e),
-            LogLevel::ServerError,
-        )
+    /// A valid configuration serving the crate's own directory
+    fn builder() -> ServerBuilder {
+        ServerBuilder::new(env!("CARGO_MANIFEST_DIR"))
+    }
+
+    #[test]
+    fn default_configuration_is_valid() {
+        assert!(validate_config(&builder().build()).is_ok());
+    }
+
+    #[test]
+    fn zero_timeout_is_rejected() {
+        let config = builder().timeout(Duration::from_secs(0)).build();
+        let err = validate_config(&config).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let config = builder().timeout(Duration::from_millis(1)).build();
+        assert!(validate_config(&config).is_ok());
+    }
+
+    #[test]
+    fn header_size_below_the_shortest_request_is_rejected() {
+        let config = builder().max_header_size(MIN_HEADER_SIZE - 1).build();
+        let err = validate_config(&config).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let config = builder().max_header_size(MIN_HEADER_SIZE).build();
+        assert!(validate_config(&config).is_ok());
+    }
+
+    #[test]
+    fn header_block_ends_at_the_first_empty_line() {
+        let head = "GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        assert_eq!(
+            header_end(format!("{}body", head).as_bytes()),
+            Some(head.len())
+        );
+        let head = "GET / HTTP/1.1\nHost: a\n\n";
+        assert_eq!(
+            header_end(format!("{}body", head).as_bytes()),
+            Some(head.len())
+        );
+        assert_eq!(header_end(b"GET / HTTP/1.1\r\nHost: a\r\n"), None);
+        // Empty lines before the request line don't end the block
+        let head = "\r\n\nGET / HTTP/1.1\n\n";
+        assert_eq!(header_end(head.as_bytes()), Some(head.len()));
+        assert_eq!(header_end(b"\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn certificate_without_key_is_rejected() {
+        let mut config = builder().build();
+        config.cert = Some(String::from("cert.pem"));
+        let err = validate_config(&config).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let config = builder().tls("cert.pem", "key.pem").build();
+        assert!(validate_config(&config).is_ok());
+    }
+
+    #[test]
+    fn client_disconnects_are_client_errors() {
+        for kind in [
+            io::ErrorKind::BrokenPipe,
+            io::ErrorKind::ConnectionReset,
+            io::ErrorKind::ConnectionAborted,
+        ] {
+            let level = send_error_level(&io::Error::from(kind));
+            assert!(matches!(level, LogLevel::ClientError), "{:?}", kind);
+        }
+        for kind in [io::ErrorKind::TimedOut, io::ErrorKind::PermissionDenied] {
+            let level = send_error_level(&io::Error::from(kind));
+            assert!(matches!(level, LogLevel::ServerError), "{:?}", kind);
+        }
     }
 }
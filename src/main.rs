@@ -1,15 +1,59 @@
-use clap::{value_t, App, Arg};
-use http_server::Arguments;
+use clap::{value_t, App, Arg, ArgMatches};
+use http_server::server::SERVER_NAME;
+use http_server::{
+    Arguments, ListingStyle, LogFormat, LogLevel, ShutdownHandle, MIN_HEADER_SIZE, MIN_STACK_SIZE,
+};
+use std::env;
+use std::net::IpAddr;
+use std::process;
+use std::sync::OnceLock;
+use std::time::Duration;
 
-fn main() {
-    let args = App::new("http-server")
+/// Stopped by the signal handler when the server has been asked to shut down
+static SHUTDOWN: OnceLock<ShutdownHandle> = OnceLock::new();
+
+extern "C" fn handle_shutdown_signal(_signal: libc::c_int) {
+    if let Some(handle) = SHUTDOWN.get() {
+        handle.stop();
+    }
+}
+
+/// Registers `handle_shutdown_signal` for SIGINT and SIGTERM.
+fn install_signal_handlers() {
+    let handler = handle_shutdown_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // Safety: the handler only reads the initialized handle and performs an atomic store,
+    // which are async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Returns the command line interface of the server.
+fn app() -> App<'static, 'static> {
+    App::new("http-server")
         .version("0.1.0")
         .arg(
             Arg::with_name("DIRECTORY")
-                .help("The directory to serve. Should contain an index.html at minimum")
+                .help("The directory to serve. Should contain an index.html at minimum, or be a single file to serve")
                 .required(true)
                 .index(1),
         )
+        .arg(
+            Arg::with_name("root")
+                .long("root")
+                .value_name("DIRECTORY")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Another directory to serve files from when they aren't found in the previous ones"),
+        )
+        .arg(
+            Arg::with_name("host")
+                .short("H")
+                .long("host")
+                .help("Address to bind the server to. Use 0.0.0.0 to listen on all interfaces")
+                .default_value("127.0.0.1"),
+        )
         .arg(
             Arg::with_name("port")
                 .short("p")
@@ -22,14 +66,560 @@ fn main() {
                 .short("t")
                 .long("threads")
                 .help("Number of threads to allocate for request handling")
-                .default_value("2"),
+                .default_value("2")
+                .validator(|value| match value.parse::<usize>() {
+                    Ok(threads) if threads >= 1 => Ok(()),
+                    _ => Err(String::from("must be a positive integer")),
+                }),
+        )
+        .arg(
+            Arg::with_name("queue-size")
+                .long("queue-size")
+                .help("Number of connections that may wait for a thread before new ones are rejected")
+                .default_value("64")
+                .validator(|value| match value.parse::<usize>() {
+                    Ok(size) if size >= 1 => Ok(()),
+                    _ => Err(String::from("must be a positive integer")),
+                }),
+        )
+        .arg(
+            Arg::with_name("keep-alive-timeout")
+                .long("keep-alive-timeout")
+                .help("Seconds to keep an idle connection open for the next request")
+                .default_value("5")
+                .validator(|value| match value.parse::<u64>() {
+                    Ok(seconds) if seconds >= 1 => Ok(()),
+                    _ => Err(String::from("must be a positive integer")),
+                }),
+        )
+        .arg(
+            Arg::with_name("keep-alive-max")
+                .long("keep-alive-max")
+                .takes_value(true)
+                .value_name("COUNT")
+                .help("Maximum number of requests on one connection before it is closed")
+                .validator(|value| match value.parse::<usize>() {
+                    Ok(max) if max >= 1 => Ok(()),
+                    _ => Err(String::from("must be a positive integer")),
+                }),
+        )
+        .arg(
+            Arg::with_name("backlog")
+                .long("backlog")
+                .help("Number of connections the OS queues until they are accepted, capped by the OS")
+                .default_value("128")
+                .validator(|value| match value.parse::<u32>() {
+                    Ok(backlog) if backlog >= 1 => Ok(()),
+                    _ => Err(String::from("must be a positive integer")),
+                }),
+        )
+        .arg(
+            Arg::with_name("max-connections")
+                .long("max-connections")
+                .takes_value(true)
+                .value_name("COUNT")
+                .help("Maximum number of open connections before new ones are rejected")
+                .validator(|value| match value.parse::<usize>() {
+                    Ok(max) if max >= 1 => Ok(()),
+                    _ => Err(String::from("must be a positive integer")),
+                }),
+        )
+        .arg(
+            Arg::with_name("stack-size")
+                .long("stack-size")
+                .takes_value(true)
+                .value_name("BYTES")
+                .help("Stack size of each worker thread")
+                .validator(|value| match value.parse::<usize>() {
+                    Ok(size) if size >= MIN_STACK_SIZE => Ok(()),
+                    _ => Err(format!("must be at least {}", MIN_STACK_SIZE)),
+                }),
+        )
+        .arg(
+            Arg::with_name("retry-after")
+                .long("retry-after")
+                .value_name("SECONDS")
+                .help("Seconds clients are told to wait before retrying when the queue is full")
+                .default_value("5"),
+        )
+        .arg(
+            Arg::with_name("max-header-size")
+                .long("max-header-size")
+                .help("Maximum size of the request headers in bytes")
+                .default_value("8192")
+                .validator(|value| match value.parse::<usize>() {
+                    Ok(size) if size >= MIN_HEADER_SIZE => Ok(()),
+                    _ => Err(format!("must be at least {}", MIN_HEADER_SIZE)),
+                }),
+        )
+        .arg(
+            Arg::with_name("max-body-size")
+                .long("max-body-size")
+                .help("Maximum size of a request body in bytes")
+                .default_value("1048576"),
+        )
+        .arg(
+            Arg::with_name("auth")
+                .long("auth")
+                .help("Require clients to log in with these credentials")
+                .takes_value(true)
+                .value_name("USER:PASSWORD")
+                .validator(|value| {
+                    if value.contains(':') {
+                        Ok(())
+                    } else {
+                        Err(String::from("must be a user name and a password separated by ':'"))
+                    }
+                }),
+        )
+        .arg(
+            Arg::with_name("cert")
+                .long("cert")
+                .help("Serve HTTPS with this PEM certificate chain, together with --key")
+                .takes_value(true)
+                .value_name("FILE")
+                .requires("key"),
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .help("PEM private key of the --cert certificate")
+                .takes_value(true)
+                .value_name("FILE")
+                .requires("cert"),
+        )
+        .arg(
+            Arg::with_name("url-prefix")
+                .long("url-prefix")
+                .help("Serve the files under this path prefix, e.g. /static")
+                .takes_value(true)
+                .value_name("PATH")
+                .validator(|value| {
+                    if value.starts_with('/') {
+                        Ok(())
+                    } else {
+                        Err(String::from("must start with a slash"))
+                    }
+                }),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .help("Reload the listing template, redirect rules and MIME types when their files change"),
+        )
+        .arg(
+            Arg::with_name("redirects")
+                .long("redirects")
+                .help("File of redirect rules, one '<path> <target> [301|302]' per line")
+                .takes_value(true)
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("mime-types")
+                .long("mime-types")
+                .help("File of Content-Types, one '<extension> <type>' per line, used before the built-in ones")
+                .takes_value(true)
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::with_name("upload-dir")
+                .long("upload-dir")
+                .help("Accept PUT and POST uploads into this directory")
+                .takes_value(true)
+                .value_name("DIRECTORY"),
+        )
+        .arg(
+            Arg::with_name("min-compress-size")
+                .long("min-compress-size")
+                .help("Smallest response body in bytes that is compressed")
+                .default_value("1024"),
+        )
+        .arg(
+            Arg::with_name("compression-level")
+                .long("compression-level")
+                .help("Compression level from 0 (none) to 9 (smallest but slowest)")
+                .default_value("6")
+                .validator(|value| match value.parse::<u32>() {
+                    Ok(level) if level <= 9 => Ok(()),
+                    _ => Err(String::from("must be an integer from 0 to 9")),
+                }),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .help("Seconds to wait on a stalled client before closing the connection")
+                .default_value("30")
+                .validator(|value| match value.parse::<u64>() {
+                    Ok(seconds) if seconds >= 1 => Ok(()),
+                    _ => Err(String::from("must be a positive integer")),
+                }),
+        )
+        .arg(
+            Arg::with_name("access-log")
+                .long("access-log")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("File to write an access log to, in the Common Log Format"),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .help("Lowest level of messages to log")
+                .possible_values(&["info", "warn", "error", "quiet"])
+                .default_value("info"),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .help("Format of the log messages")
+                .possible_values(&["text", "json"])
+                .default_value("text"),
         )
-        .get_matches();
+        .arg(Arg::with_name("no-color").long("no-color").help(
+            "Don't color the log messages, also set by the NO_COLOR environment variable",
+        ))
+        .arg(
+            Arg::with_name("server-header")
+                .long("server-header")
+                .takes_value(true)
+                .value_name("VALUE")
+                .help("Value of the Server response header, or an empty string to leave it out"),
+        )
+        .arg(
+            Arg::with_name("index")
+                .long("index")
+                .value_name("FILES")
+                .help("Comma-separated file names to serve for a directory, tried in order")
+                .default_value("index.html"),
+        )
+        .arg(
+            Arg::with_name("canonical-index")
+                .long("canonical-index")
+                .help("Redirect requests for an index file, e.g. /index.html, to its directory"),
+        )
+        .arg(
+            Arg::with_name("download-types")
+                .long("download-types")
+                .takes_value(true)
+                .value_name("EXTENSIONS")
+                .help("Comma-separated file extensions that browsers download instead of displaying, e.g. zip,pdf"),
+        )
+        .arg(
+            Arg::with_name("charset")
+                .long("charset")
+                .help("Character encoding of the served text files")
+                .default_value("UTF-8"),
+        )
+        .arg(
+            Arg::with_name("cors-origin")
+                .long("cors-origin")
+                .takes_value(true)
+                .value_name("ORIGIN")
+                .help("Allow cross-origin requests from the given origin, or * for any origin"),
+        )
+        .arg(
+            Arg::with_name("nosniff")
+                .long("nosniff")
+                .help("Send X-Content-Type-Options: nosniff to prevent MIME type sniffing"),
+        )
+        .arg(
+            Arg::with_name("frame-options")
+                .long("frame-options")
+                .takes_value(true)
+                .possible_values(&["DENY", "SAMEORIGIN"])
+                .help("Value of the X-Frame-Options header, to prevent clickjacking"),
+        )
+        .arg(
+            Arg::with_name("content-security-policy")
+                .long("content-security-policy")
+                .takes_value(true)
+                .value_name("POLICY")
+                .help("Value of the Content-Security-Policy header"),
+        )
+        .arg(
+            Arg::with_name("no-symlinks")
+                .long("no-symlinks")
+                .help("Don't serve files through symbolic links. By default, links that stay inside DIRECTORY are followed"),
+        )
+        .arg(
+            Arg::with_name("no-cache")
+                .long("no-cache")
+                .conflicts_with("cache-max-age")
+                .help("Tell browsers not to cache anything, so changes show up immediately"),
+        )
+        .arg(
+            Arg::with_name("cache-max-age")
+                .long("cache-max-age")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Let browsers cache every file for SECONDS, instead of a default for its type"),
+        )
+        .arg(
+            Arg::with_name("cache-size")
+                .long("cache-size")
+                .takes_value(true)
+                .value_name("BYTES")
+                .help("Keep up to BYTES of recently served files in memory"),
+        )
+        .arg(
+            Arg::with_name("spa")
+                .long("spa")
+                .help("Serve the root index.html for missing paths without a file extension, for single-page apps"),
+        )
+        .arg(
+            Arg::with_name("favicon")
+                .long("favicon")
+                .help("Serve a built-in icon for /favicon.ico when DIRECTORY doesn't contain one"),
+        )
+        .arg(
+            Arg::with_name("trust-proxy")
+                .long("trust-proxy")
+                .help("Log the client address from the X-Forwarded-For header, when behind a reverse proxy"),
+        )
+        .arg(
+            Arg::with_name("allowed-host")
+                .long("allowed-host")
+                .value_name("HOST")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only respond to requests for HOST, without the port. Can be given multiple times"),
+        )
+        .arg(
+            Arg::with_name("health-path")
+                .long("health-path")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Respond to requests for PATH with 200 OK, for health checks"),
+        )
+        .arg(
+            Arg::with_name("metrics-path")
+                .long("metrics-path")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Respond to requests for PATH with the server's traffic counters"),
+        )
+        .arg(
+            Arg::with_name("summary-interval")
+                .long("summary-interval")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Log a summary of the served requests every SECONDS seconds")
+                .validator(|value| match value.parse::<u64>() {
+                    Ok(seconds) if seconds >= 1 => Ok(()),
+                    _ => Err(String::from("must be a positive integer")),
+                }),
+        )
+        .arg(
+            Arg::with_name("autoindex")
+                .long("autoindex")
+                .help("List the contents of directories that don't contain an index.html"),
+        )
+        .arg(
+            Arg::with_name("dir-listing-style")
+                .long("dir-listing-style")
+                .help("Look of the directory listings")
+                .possible_values(&["plain", "styled"])
+                .default_value("plain"),
+        )
+        .arg(
+            Arg::with_name("listing-template")
+                .long("listing-template")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("HTML file to make directory listings from, with {title} and {rows} placeholders"),
+        )
+}
 
-    let config = Arguments {
-        directory: String::from(args.value_of("DIRECTORY").unwrap()),
+/// Returns the server configuration for the parsed command line, without a shutdown
+/// handle. Exits with a usage error if a value can't be parsed.
+fn arguments(args: &ArgMatches) -> Arguments {
+    Arguments {
+        directories: std::iter::once(args.value_of("DIRECTORY").unwrap())
+            .chain(args.values_of("root").into_iter().flatten())
+            .map(String::from)
+            .collect(),
+        host: value_t!(args.value_of("host"), IpAddr).unwrap_or_else(|e| e.exit()),
         port: value_t!(args.value_of("port"), u16).unwrap_or_else(|e| e.exit()),
         threads: value_t!(args.value_of("threads"), usize).unwrap_or_else(|e| e.exit()),
-    };
-    http_server::run(config);
+        queue_size: value_t!(args.value_of("queue-size"), usize).unwrap_or_else(|e| e.exit()),
+        stack_size: args
+            .value_of("stack-size")
+            .map(|_| value_t!(args.value_of("stack-size"), usize).unwrap_or_else(|e| e.exit())),
+        backlog: value_t!(args.value_of("backlog"), u32).unwrap_or_else(|e| e.exit()),
+        max_connections: args.value_of("max-connections").map(|_| {
+            value_t!(args.value_of("max-connections"), usize).unwrap_or_else(|e| e.exit())
+        }),
+        max_header_size: value_t!(args.value_of("max-header-size"), usize)
+            .unwrap_or_else(|e| e.exit()),
+        max_body_size: value_t!(args.value_of("max-body-size"), u64).unwrap_or_else(|e| e.exit()),
+        upload_dir: args.value_of("upload-dir").map(String::from),
+        redirects: args.value_of("redirects").map(String::from),
+        mime_types: args.value_of("mime-types").map(String::from),
+        watch: args.is_present("watch"),
+        auth: args.value_of("auth").map(String::from),
+        cert: args.value_of("cert").map(String::from),
+        key: args.value_of("key").map(String::from),
+        url_prefix: args
+            .value_of("url-prefix")
+            .map(|prefix| prefix.trim_end_matches('/'))
+            .filter(|prefix| !prefix.is_empty())
+            .map(String::from),
+        compression_level: value_t!(args.value_of("compression-level"), u32)
+            .unwrap_or_else(|e| e.exit()),
+        min_compress_size: value_t!(args.value_of("min-compress-size"), usize)
+            .unwrap_or_else(|e| e.exit()),
+        autoindex: args.is_present("autoindex"),
+        listing_style: match args.value_of("dir-listing-style") {
+            Some("styled") => ListingStyle::Styled,
+            _ => ListingStyle::Plain,
+        },
+        listing_template: args.value_of("listing-template").map(String::from),
+        spa: args.is_present("spa"),
+        favicon: args.is_present("favicon"),
+        follow_symlinks: !args.is_present("no-symlinks"),
+        no_cache: args.is_present("no-cache"),
+        cache_size: args
+            .value_of("cache-size")
+            .map(|_| value_t!(args.value_of("cache-size"), u64).unwrap_or_else(|e| e.exit())),
+        cache_max_age: args
+            .value_of("cache-max-age")
+            .map(|_| value_t!(args.value_of("cache-max-age"), u32).unwrap_or_else(|e| e.exit())),
+        cors_origin: args.value_of("cors-origin").map(String::from),
+        trust_proxy: args.is_present("trust-proxy"),
+        allowed_hosts: args
+            .values_of("allowed-host")
+            .into_iter()
+            .flatten()
+            .map(String::from)
+            .collect(),
+        health_path: args.value_of("health-path").map(String::from),
+        metrics_path: args.value_of("metrics-path").map(String::from),
+        summary_interval: args.value_of("summary-interval").map(|_| {
+            Duration::from_secs(
+                value_t!(args.value_of("summary-interval"), u64).unwrap_or_else(|e| e.exit()),
+            )
+        }),
+        nosniff: args.is_present("nosniff"),
+        frame_options: args.value_of("frame-options").map(String::from),
+        content_security_policy: args.value_of("content-security-policy").map(String::from),
+        shutdown: None,
+        handler: None,
+        transformers: Vec::new(),
+        canonical_index: args.is_present("canonical-index"),
+        index_files: args
+            .value_of("index")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect(),
+        download_types: args
+            .value_of("download-types")
+            .unwrap_or_default()
+            .split(',')
+            .map(|ext| ext.trim().trim_start_matches('.'))
+            .filter(|ext| !ext.is_empty())
+            .map(String::from)
+            .collect(),
+        charset: args.value_of("charset").unwrap_or_default().to_string(),
+        server_header: match args.value_of("server-header") {
+            Some("") => None,
+            Some(value) => Some(value.to_string()),
+            None => Some(SERVER_NAME.to_string()),
+        },
+        access_log: args.value_of("access-log").map(String::from),
+        log_level: match args.value_of("log-level") {
+            Some("warn") => Some(LogLevel::ClientError),
+            Some("error") => Some(LogLevel::ServerError),
+            Some("quiet") => None,
+            _ => Some(LogLevel::Info),
+        },
+        log_format: match args.value_of("log-format") {
+            Some("json") => LogFormat::Json,
+            _ => LogFormat::Text,
+        },
+        // Colors are only useful on a terminal, see https://no-color.org
+        log_color: !args.is_present("no-color")
+            && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            && atty::is(atty::Stream::Stdout),
+        timeout: Duration::from_secs(
+            value_t!(args.value_of("timeout"), u64).unwrap_or_else(|e| e.exit()),
+        ),
+        keep_alive_timeout: Duration::from_secs(
+            value_t!(args.value_of("keep-alive-timeout"), u64).unwrap_or_else(|e| e.exit()),
+        ),
+        keep_alive_max: args
+            .value_of("keep-alive-max")
+            .map(|_| value_t!(args.value_of("keep-alive-max"), usize).unwrap_or_else(|e| e.exit())),
+        retry_after: Duration::from_secs(
+            value_t!(args.value_of("retry-after"), u64).unwrap_or_else(|e| e.exit()),
+        ),
+    }
+}
+
+fn main() {
+    let mut config = arguments(&app().get_matches());
+    config.shutdown = Some(SHUTDOWN.get_or_init(ShutdownHandle::new).clone());
+    install_signal_handlers();
+    if http_server::run(config).is_err() {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the configuration for the command line arguments after the program name.
+    fn parse(args: &[&str]) -> Arguments {
+        let args = std::iter::once("http-server").chain(args.iter().copied());
+        arguments(&app().get_matches_from(args))
+    }
+
+    #[test]
+    fn defaults_match_the_builder() {
+        let config = parse(&["public"]);
+        assert_eq!(config.directories, ["public"]);
+        assert_eq!(config.host, IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(config.port, 80);
+        assert_eq!(config.threads, 2);
+        assert_eq!(config.index_files, ["index.html"]);
+        assert_eq!(config.server_header.as_deref(), Some(SERVER_NAME));
+        assert!(config.shutdown.is_none());
+    }
+
+    #[test]
+    fn options_set_their_fields() {
+        let config = parse(&[
+            "public",
+            "--threads",
+            "8",
+            "--port",
+            "8080",
+            "--host",
+            "::",
+            "--root",
+            "static",
+            "--index",
+            "home.html, index.htm",
+            "--download-types",
+            ".zip,pdf",
+            "--url-prefix",
+            "/static/",
+            "--keep-alive-max",
+            "10",
+            "--log-level",
+            "quiet",
+        ]);
+        assert_eq!(config.threads, 8);
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.host, "::".parse::<IpAddr>().unwrap());
+        assert_eq!(config.directories, ["public", "static"]);
+        assert_eq!(config.index_files, ["home.html", "index.htm"]);
+        assert_eq!(config.download_types, ["zip", "pdf"]);
+        assert_eq!(config.url_prefix.as_deref(), Some("/static"));
+        assert_eq!(config.keep_alive_max, Some(10));
+        assert!(config.log_level.is_none());
+    }
 }
@@ -0,0 +1,99 @@
+use crate::server::Request;
+use chrono::Local;
+use std::fs::{File, OpenOptions};
+use std::io::{Result, Write};
+use std::sync::Mutex;
+
+/// Access log file with one line per request, in the Common Log Format
+pub struct AccessLog {
+    file: Mutex<File>,
+}
+
+impl AccessLog {
+    /// Opens the access log at the given path, creating the file if it doesn't exist.
+    /// New entries are appended to the end of the file.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the log file
+    pub fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AccessLog {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends an entry for a handled request to the log.
+    ///
+    /// # Arguments
+    /// * `client` - Address of the client that sent the request
+    /// * `request` - The handled request
+    /// * `status` - The response's HTTP status code
+    /// * `bytes` - The number of body bytes sent in the response
+    pub fn record(&self, client: &str, request: &Request, status: u16, bytes: u64) -> Result<()> {
+        let target = match request.query {
+            Some(query) => format!("{}?{}", request.path, query),
+            None => request.path.to_string(),
+        };
+        let size = match bytes {
+            0 => String::from("-"),
+            _ => bytes.to_string(),
+        };
+        let line = format!(
+            "{} - - [{}] \"{} {} {}\" {} {}\n",
+            client,
+            Local::now().format("%d/%b/%Y:%H:%M:%S %z"),
+            request.method_str(),
+            target,
+            request.protocol,
+            status,
+            size
+        );
+        // Each entry is written in one call while holding the lock, so lines never interleave
+        match self.file.lock() {
+            Ok(mut file) => file.write_all(line.as_bytes()),
+            Err(poisoned) => poisoned.into_inner().write_all(line.as_bytes()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use std::fs;
+
+    #[test]
+    fn entries_use_the_common_log_format() {
+        let path = std::env::temp_dir().join(format!("access-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let log = AccessLog::open(path.to_str().unwrap()).unwrap();
+        let request =
+            Request::parse("GET /docs/a.txt?v=2 HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        log.record("127.0.0.1", &request, 200, 1234).unwrap();
+        log.record("::1", &request, 304, 0).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for (line, host, end) in &[
+            (
+                lines[0],
+                "127.0.0.1",
+                "\"GET /docs/a.txt?v=2 HTTP/1.1\" 200 1234",
+            ),
+            (lines[1], "::1", "\"GET /docs/a.txt?v=2 HTTP/1.1\" 304 -"),
+        ] {
+            let (start, rest) = line.split_once(" [").unwrap();
+            assert_eq!(start, format!("{} - -", host));
+            // e.g. [10/Oct/2000:13:55:36 -0700]
+            let (date, rest) = rest.split_once("] ").unwrap();
+            assert!(
+                DateTime::parse_from_str(date, "%d/%b/%Y:%H:%M:%S %z").is_ok(),
+                "{}",
+                date
+            );
+            assert_eq!(rest, *end);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+/// Characters of the base64 alphabet, indexed by their 6-bit value
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Credentials required in the `Authorization` header, using the Basic scheme
+///
+/// * `token` - The base64-encoded `user:password` pair the client must send
+pub struct BasicAuth {
+    token: String,
+}
+
+impl BasicAuth {
+    /// Returns a new `BasicAuth` instance accepting the given credentials.
+    ///
+    /// # Arguments
+    /// * `credentials` - The user name and password separated by a colon, e.g. `user:secret`
+    pub fn new(credentials: &str) -> Self {
+        BasicAuth {
+            token: base64_encode(credentials.as_bytes()),
+        }
+    }
+
+    /// Returns whether the value of a request's `Authorization` header has the credentials.
+    /// The credentials are compared in constant time, so the comparison doesn't reveal
+    /// how much of a guess was right.
+    pub fn authorizes(&self, header: Option<&str>) -> bool {
+        let header = match header {
+            Some(header) => header.trim(),
+            None => return false,
+        };
+        match header.split_once(' ') {
+            // The scheme is case-insensitive, the credentials are not
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("Basic") => {
+                constant_time_eq(token.trim().as_bytes(), self.token.as_bytes())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Returns whether the byte strings are equal, taking the same time for any strings of
+/// the same length.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Returns the bytes encoded as base64, with padding.
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | u32::from(*byte) << (16 - 8 * i)
+        });
+        // Three bytes make four characters, a shorter chunk is padded with `=`
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (group >> (18 - 6 * i)) & 0x3f;
+                encoded.push(char::from(BASE64_ALPHABET[index as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_matches_rfc_4648_test_vectors() {
+        for (input, encoded) in &[
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64_encode(input.as_bytes()), *encoded, "{}", input);
+        }
+        // Bytes that map to the last characters of the alphabet
+        assert_eq!(base64_encode(&[0xfb, 0xff, 0xbf]), "+/+/");
+    }
+
+    #[test]
+    fn only_the_configured_credentials_are_authorized() {
+        // "Aladdin:open sesame", the example of RFC 7617
+        let auth = BasicAuth::new("Aladdin:open sesame");
+        assert!(auth.authorizes(Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==")));
+        assert!(auth.authorizes(Some("basic  QWxhZGRpbjpvcGVuIHNlc2FtZQ== ")));
+        assert!(!auth.authorizes(Some("Basic qwxhzgrpbjpvcgvuihnlc2ftzq==")));
+        assert!(!auth.authorizes(Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ")));
+        assert!(!auth.authorizes(Some("Bearer QWxhZGRpbjpvcGVuIHNlc2FtZQ==")));
+        assert!(!auth.authorizes(Some("QWxhZGRpbjpvcGVuIHNlc2FtZQ==")));
+        assert!(!auth.authorizes(None));
+    }
+}
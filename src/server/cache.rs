@@ -0,0 +1,270 @@
+use crate::server::{compress, HttpContent};
+use std::collections::HashMap;
+use std::io::Result;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Contents of recently served files, kept in memory up to a total size in bytes.
+/// Compressed variants of the files are cached separately from the original contents.
+/// When the cache is full, the least recently used entries are evicted first.
+///
+/// * `capacity` - The maximum total size of the cached files in bytes
+pub struct FileCache {
+    capacity: u64,
+    inner: Mutex<CacheState>,
+}
+
+/// Path of a cached file, and the content encoding of the cached bytes if they are compressed
+type CacheKey = (String, Option<&'static str>);
+
+/// The cached files and the bookkeeping for evicting them
+///
+/// * `entries` - Cached files by their path and encoding
+/// * `size` - The total size of the cached files in bytes
+/// * `clock` - Incremented on every lookup, to order the entries by their last use
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, CacheEntry>,
+    size: u64,
+    clock: u64,
+}
+
+/// A cached file
+///
+/// * `bytes` - The file's contents
+/// * `modified` - The file's modification time when it was read
+/// * `last_used` - Value of the cache's clock when the entry was last looked up
+struct CacheEntry {
+    bytes: Vec<u8>,
+    modified: SystemTime,
+    last_used: u64,
+}
+
+impl FileCache {
+    /// Returns an empty cache.
+    ///
+    /// # Arguments
+    /// * `capacity` - The maximum total size of the cached files in bytes
+    pub fn new(capacity: u64) -> Self {
+        FileCache {
+            capacity,
+            inner: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Returns the file's contents from the cache, or reads them from disk if they aren't
+    /// cached or the file has been modified since. Files read from disk are cached if they fit.
+    /// Returns `io::Error` if the file could not be read.
+    pub fn get_bytes(&self, content: &HttpContent) -> Result<Vec<u8>> {
+        // Without a modification time, changes to the file can't be noticed
+        let modified = match content.modified() {
+            Some(modified) => modified,
+            None => return content.get_bytes(),
+        };
+        let key = (content.path().to_string(), None);
+        if let Some(bytes) = self.lookup(&key, modified) {
+            return Ok(bytes);
+        }
+        let bytes = content.get_bytes()?;
+        self.insert(key, &bytes, modified);
+        Ok(bytes)
+    }
+
+    /// Returns the file's contents compressed with the given content encoding, from the cache
+    /// if the file hasn't been modified since it was compressed. The compressed and the
+    /// original contents are cached if they fit.
+    /// Returns `io::Error` if the file could not be read or compressed.
+    ///
+    /// # Arguments
+    /// * `content` - The file
+    /// * `encoding` - The content encoding to compress with
+    /// * `level` - Compression level, from 0 (none) to 9 (smallest but slowest)
+    pub fn get_compressed(
+        &self,
+        content: &HttpContent,
+        encoding: &'static str,
+        level: u32,
+    ) -> Result<Vec<u8>> {
+        let modified = match content.modified() {
+            Some(modified) => modified,
+            None => return compress(&content.get_bytes()?, encoding, level),
+        };
+        let key = (content.path().to_string(), Some(encoding));
+        if let Some(bytes) = self.lookup(&key, modified) {
+            return Ok(bytes);
+        }
+        let bytes = compress(&self.get_bytes(content)?, encoding, level)?;
+        self.insert(key, &bytes, modified);
+        Ok(bytes)
+    }
+
+    /// Drops the file's original and compressed contents from the cache, so they are read
+    /// from disk the next time. Returns whether anything was cached for the file.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file, including the served folder
+    pub fn invalidate(&self, path: &Path) -> bool {
+        let mut state = match self.inner.lock() {
+            Ok(state) => state,
+            Err(_) => return false,
+        };
+        let count = state.entries.len();
+        let mut removed = 0;
+        state.entries.retain(|(cached, _), entry| {
+            let keep = Path::new(cached) != path;
+            if !keep {
+                removed += entry.bytes.len() as u64;
+            }
+            keep
+        });
+        state.size -= removed;
+        state.entries.len() < count
+    }
+
+    /// Returns the cached bytes for the key, if they are as new as `modified`.
+    fn lookup(&self, key: &CacheKey, modified: SystemTime) -> Option<Vec<u8>> {
+        let mut state = self.inner.lock().ok()?;
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = state.entries.get_mut(key)?;
+        if entry.modified != modified {
+            return None;
+        }
+        entry.last_used = clock;
+        Some(entry.bytes.clone())
+    }
+
+    /// Caches the bytes for the key, evicting the least recently used entries until they fit.
+    /// Anything larger than the whole cache is not cached.
+    fn insert(&self, key: CacheKey, bytes: &[u8], modified: SystemTime) {
+        let size = bytes.len() as u64;
+        if size > self.capacity {
+            return;
+        }
+        let mut state = match self.inner.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        if let Some(old) = state.entries.remove(&key) {
+            state.size -= old.bytes.len() as u64;
+        }
+        while state.size + size > self.capacity {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            match oldest.and_then(|key| state.entries.remove(&key)) {
+                Some(evicted) => state.size -= evicted.bytes.len() as u64,
+                None => break,
+            }
+        }
+        let last_used = state.clock;
+        state.size += size;
+        state.entries.insert(
+            key,
+            CacheEntry {
+                bytes: bytes.to_vec(),
+                modified,
+                last_used,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    /// Returns an empty directory for the test, with a file of four bytes for each name.
+    fn folder(name: &str, files: &[&str]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("cache-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        for file in files {
+            fs::write(root.join(file), "1234").unwrap();
+        }
+        root
+    }
+
+    fn content(root: &Path, name: &str) -> HttpContent {
+        HttpContent::new(root.to_str().unwrap(), name, &[], true).unwrap()
+    }
+
+    fn is_cached(cache: &FileCache, content: &HttpContent) -> bool {
+        let key = (content.path().to_string(), None);
+        cache.inner.lock().unwrap().entries.contains_key(&key)
+    }
+
+    #[test]
+    fn least_recently_used_files_are_evicted_first() {
+        let root = folder("evict", &["a", "b", "c"]);
+        let cache = FileCache::new(10);
+        let (a, b, c) = (
+            content(&root, "a"),
+            content(&root, "b"),
+            content(&root, "c"),
+        );
+        cache.get_bytes(&a).unwrap();
+        cache.get_bytes(&b).unwrap();
+        cache.get_bytes(&a).unwrap();
+        cache.get_bytes(&c).unwrap();
+        assert!(is_cached(&cache, &a));
+        assert!(!is_cached(&cache, &b));
+        assert!(is_cached(&cache, &c));
+        assert_eq!(cache.inner.lock().unwrap().size, 8);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn files_larger_than_the_cache_are_not_cached() {
+        let root = folder("large", &["a"]);
+        let cache = FileCache::new(3);
+        let a = content(&root, "a");
+        assert_eq!(cache.get_bytes(&a).unwrap(), b"1234");
+        assert!(!is_cached(&cache, &a));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn modified_files_are_read_again() {
+        let root = folder("modified", &["a"]);
+        let cache = FileCache::new(100);
+        let a = content(&root, "a");
+        let modified = a.modified().unwrap();
+        cache.get_bytes(&a).unwrap();
+        let file = fs::File::options()
+            .write(true)
+            .open(root.join("a"))
+            .unwrap();
+        file.set_len(0).unwrap();
+        // The cached bytes are used while the modification time stays the same
+        file.set_modified(modified).unwrap();
+        assert_eq!(cache.get_bytes(&a).unwrap(), b"1234");
+        file.set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(cache.get_bytes(&a).unwrap(), b"");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn invalidated_files_are_dropped_with_their_compressed_variants() {
+        let root = folder("invalidate", &["a", "b"]);
+        let cache = FileCache::new(1024);
+        let (a, b) = (content(&root, "a"), content(&root, "b"));
+        cache.get_compressed(&a, "gzip", 6).unwrap();
+        cache.get_bytes(&b).unwrap();
+        assert!(cache.invalidate(Path::new(a.path())));
+        assert!(!cache.invalidate(Path::new(a.path())));
+        let state = cache.inner.lock().unwrap();
+        assert_eq!(state.entries.len(), 1);
+        assert_eq!(state.size, 4);
+        drop(state);
+        assert!(is_cached(&cache, &b));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,34 +1,183 @@
+use std::borrow::Cow;
+use std::fs;
 use std::fs::File;
 use std::io::Read;
-use std::io::Result;
-use std::path::Path;
+use std::io::{Error, ErrorKind, Result};
+use std::io::{Seek, SeekFrom, Take};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use urlencoding::decode;
 
+const MINUTE: u32 = 60;
+const HOUR: u32 = 3600;
+const DAY: u32 = 86400;
+
+/// Content-Type, Cache-Control max-age and gzip compression for known file extensions.
+/// Files with any other extension are served as `application/octet-stream`.
+/// Text types get the configured charset appended, see `is_text_type`.
+const CONTENT_TYPES: &[(&str, &str, u32, bool)] = &[
+    // Documents
+    ("html", "text/html", MINUTE, true),
+    ("htm", "text/html", MINUTE, true),
+    ("txt", "text/plain", MINUTE, true),
+    ("csv", "text/csv", MINUTE, true),
+    ("xml", "application/xml", HOUR, true),
+    ("json", "application/json", HOUR, true),
+    ("webmanifest", "application/manifest+json", HOUR, true),
+    ("pdf", "application/pdf", DAY, false),
+    // Scripts and styles
+    ("css", "text/css", 3 * DAY, true),
+    ("js", "text/javascript", 3 * DAY, true),
+    ("mjs", "text/javascript", 3 * DAY, true),
+    ("map", "application/json", 3 * DAY, true),
+    ("wasm", "application/wasm", 3 * DAY, true),
+    // Images
+    ("svg", "image/svg+xml", 7 * DAY, true),
+    ("ico", "image/x-icon", 7 * DAY, false),
+    ("png", "image/png", 3 * DAY, false),
+    ("jpg", "image/jpeg", 3 * DAY, false),
+    ("jpeg", "image/jpeg", 3 * DAY, false),
+    ("gif", "image/gif", 3 * DAY, false),
+    ("webp", "image/webp", 3 * DAY, false),
+    ("avif", "image/avif", 3 * DAY, false),
+    // Fonts. WOFF files are already compressed
+    ("otf", "font/otf", 7 * DAY, true),
+    ("ttf", "font/ttf", 7 * DAY, true),
+    ("woff", "font/woff", 7 * DAY, false),
+    ("woff2", "font/woff2", 7 * DAY, false),
+    // Media
+    ("mp4", "video/mp4", DAY, false),
+    ("webm", "video/webm", DAY, false),
+    ("mp3", "audio/mp3", DAY, false),
+    ("ogg", "audio/ogg", DAY, false),
+];
+
+/// How the Content-Types of the served files are chosen, and which files are downloaded
+///
+/// * `charset` - Character encoding of the served text files, sent as the `charset` of
+///   their Content-Type, e.g. `ISO-8859-1`
+/// * `download_types` - Extensions of the files that browsers are told to download instead
+///   of displaying, without the dot. Case-insensitive
+/// * `mime_types` - Content-Types that override or extend `CONTENT_TYPES`, as
+///   `(extension, type)` pairs, e.g. `("wasm", "application/wasm")`. The extensions are
+///   without the dot and case-insensitive
+#[derive(Clone)]
+pub struct ContentTypes {
+    pub charset: String,
+    pub download_types: Vec<String>,
+    pub mime_types: Vec<(String, String)>,
+}
+
+impl Default for ContentTypes {
+    fn default() -> Self {
+        ContentTypes {
+            charset: String::from("UTF-8"),
+            download_types: Vec::new(),
+            mime_types: Vec::new(),
+        }
+    }
+}
+
+/// Reads Content-Type overrides from the file at the given path.
+/// Returns `io::Error` if the file could not be read or contains an invalid line.
+///
+/// Each non-empty line of the file holds an extension and its MIME type, e.g.
+/// `foo application/x-foo`. Lines starting with `#` are comments.
+///
+/// # Arguments
+/// * `path` - Path of the MIME types file
+pub fn read_mime_types(path: &str) -> Result<Vec<(String, String)>> {
+    let mut types = Vec::new();
+    for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [extension, content_type] if content_type.contains('/') => {
+                let extension = extension.trim_start_matches('.');
+                types.push((extension.to_string(), content_type.to_string()));
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: expected an extension and a MIME type", number + 1),
+                ))
+            }
+        }
+    }
+    Ok(types)
+}
+
+/// Returns whether the given MIME type is text, so that a charset applies to it.
+fn is_text_type(content_type: &str) -> bool {
+    content_type.starts_with("text/")
+        || content_type.ends_with("/xml")
+        || content_type.ends_with("+xml")
+        || content_type.ends_with("/json")
+        || content_type.ends_with("+json")
+}
+
 /// Represents a file in the served folder
 pub struct HttpContent {
     file_path: String,
 }
 
 impl HttpContent {
-
     /// Returns a new HttpContent instance for the given folder and file path.
     /// Returns `None` if the file does not exist in the served folder.
     ///
     /// # Arguments
     ///
     /// * `serve_path` - The served folder
-    /// * `content_path` - Path of the requested file, relative to `serve_path`
-    pub fn new(serve_path: &str, content_path: &str) -> Option<Self> {
-        let content_path = content_path.strip_prefix(&['/', '\\'][..]).unwrap_or(content_path);
-        let combined_path = format!("{}/{}", serve_path, content_path);
-        let file_path = resolve_file_path(combined_path.to_string())?;
-        if in_serve_folder(serve_path, &file_path) {
+    /// * `content_path` - Percent-encoded path of the requested file, relative to `serve_path`
+    /// * `index_files` - File names served for a directory, tried in order
+    /// * `follow_symlinks` - Whether files may be reached through symbolic links
+    pub fn new(
+        serve_path: &str,
+        content_path: &str,
+        index_files: &[String],
+        follow_symlinks: bool,
+    ) -> Option<Self> {
+        let combined_path = combined_path(serve_path, content_path)?;
+        let file_path = resolve_file_path(combined_path, index_files)?;
+        if in_serve_folder(serve_path, &file_path, follow_symlinks) {
             Some(HttpContent { file_path })
         } else {
             None
         }
     }
 
+    /// Returns a precompressed version of the file, stored next to it with the given
+    /// extension appended to its name, e.g. `app.js.gz` for `app.js`.
+    /// Returns `None` if there is no such file in the served folder.
+    ///
+    /// # Arguments
+    ///
+    /// * `serve_path` - The served folder
+    /// * `extension` - Extension of the compressed file, e.g. `gz`
+    /// * `follow_symlinks` - Whether the file may be reached through a symbolic link
+    pub fn precompressed(
+        &self,
+        serve_path: &str,
+        extension: &str,
+        follow_symlinks: bool,
+    ) -> Option<Self> {
+        let file_path = format!("{}.{}", self.file_path, extension);
+        if Path::new(&file_path).is_file()
+            && in_serve_folder(serve_path, &file_path, follow_symlinks)
+        {
+            Some(HttpContent { file_path })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the path of the file, including the served folder.
+    pub fn path(&self) -> &str {
+        &self.file_path
+    }
+
     /// Returns the file's contents as a byte vector.
     /// Returns `io::Error` if the file could not be read.
     pub fn get_bytes(&self) -> Result<Vec<u8>> {
@@ -38,72 +187,235 @@ impl HttpContent {
         Ok(buffer)
     }
 
+    /// Opens the file for reading `length` bytes starting at offset `start`,
+    /// so that the contents can be streamed without loading them into memory.
+    /// Returns `io::Error` if the file could not be opened.
+    ///
+    /// # Arguments
+    /// * `start` - Offset of the first byte to read
+    /// * `length` - The number of bytes to read
+    pub fn open_range(&self, start: u64, length: u64) -> Result<Take<File>> {
+        let mut file = File::open(Path::new(&self.file_path))?;
+        file.seek(SeekFrom::Start(start))?;
+        Ok(file.take(length))
+    }
+
+    /// Returns the size of the file in bytes.
+    /// Returns `io::Error` if the file's metadata could not be read.
+    pub fn size(&self) -> Result<u64> {
+        Ok(fs::metadata(&self.file_path)?.len())
+    }
+
     /// Return the content type specific response headers for the file.
-    pub fn content_headers(&self) -> ContentHeaders {
+    ///
+    /// # Arguments
+    /// * `types` - The server's Content-Type settings
+    pub fn content_headers(&self, types: &ContentTypes) -> ContentHeaders<'static> {
         let ext = match Path::new(&self.file_path).extension() {
-            Some(x) => match x.to_str() {
-                Some(y) => y,
-                None => "",
-            },
+            Some(x) => x.to_str().unwrap_or_default(),
             None => "",
         };
-        let min = 60;
-        let hour = 3600;
-        let day = 86400;
-
-        let (ctype, age, use_gzip) = match ext {
-            "html" => ("text/html; charset=UTF-8", min, true),
-            "css" => ("text/css; charset=UTF-8", 3 * day, true),
-            "js" => ("text/javascript; charset=UTF-8", 3 * day, true),
-            "txt" => ("text/plain; charset=UTF-8", min, true),
-            "json" => ("application/json; charset=UTF-8", hour, true),
-            "svg" => ("image/svg+xml; charset=UTF-8", 7 * day, true),
-            "webp" => ("image/webp", 3 * day, false),
-            "jpg" | "jpeg" => ("image/jpeg", 3 * day, false),
-            "ico" => ("image/x-icon", 7 * day, false),
-            "png" => ("image/png", 3 * day, false),
-            "otf" => ("font/otf", 7 * day, true),
-            "ttf" => ("font/ttf", 7 * day, true),
-            "mp4" => ("video/mp4", day, false),
-            "mp3" => ("audio/mp3", day, false),
-            _ => ("application/octet-stream", min, false),
+        let builtin = CONTENT_TYPES
+            .iter()
+            .find(|(extension, ..)| extension.eq_ignore_ascii_case(ext))
+            .map(|(_, ctype, age, use_gzip)| (Cow::Borrowed(*ctype), *age, *use_gzip));
+        let custom = types
+            .mime_types
+            .iter()
+            .find(|(extension, _)| extension.eq_ignore_ascii_case(ext))
+            .map(|(_, ctype)| ctype.clone());
+        // A custom type keeps the caching and compression of a known extension
+        let (ctype, age, use_gzip) = match (custom, builtin) {
+            (Some(ctype), Some((_, age, use_gzip))) => (Cow::Owned(ctype), age, use_gzip),
+            (Some(ctype), None) => {
+                let use_gzip = is_text_type(&ctype);
+                (Cow::Owned(ctype), MINUTE, use_gzip)
+            }
+            (None, Some(builtin)) => builtin,
+            (None, None) => (Cow::Borrowed("application/octet-stream"), MINUTE, false),
         };
+        let charset = Some(types.charset.clone()).filter(|_| is_text_type(&ctype));
+        let download = types
+            .download_types
+            .iter()
+            .any(|extension| extension.eq_ignore_ascii_case(ext));
+        let download_name = Path::new(&self.file_path)
+            .file_name()
+            .filter(|_| download)
+            .map(|name| name.to_string_lossy().into_owned());
         ContentHeaders {
             content_type: ctype,
+            charset,
             cache_age: age,
-            compress: use_gzip
+            compress: use_gzip,
+            etag: self.etag(),
+            last_modified: self.modified(),
+            accept_ranges: true,
+            download_name,
         }
     }
+
+    /// Returns the file's last modification time.
+    /// Returns `None` if it could not be read.
+    pub fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.file_path).ok()?.modified().ok()
+    }
+
+    /// Returns a strong entity tag derived from the file's size and modification time.
+    /// Returns `None` if the file's metadata could not be read.
+    pub fn etag(&self) -> Option<String> {
+        let metadata = fs::metadata(&self.file_path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(format!(
+            "\"{:x}-{:x}-{:x}\"",
+            modified.as_secs(),
+            modified.subsec_nanos(),
+            metadata.len()
+        ))
+    }
 }
 
 /// Struct representing a file's content type specific response headers.
-/// * `content_type` - The file's Content-Type header value
+/// * `content_type` - The file's MIME type
+/// * `charset` - Character encoding of a text file, appended to the Content-Type header
 /// * `cache_age` - The file's Cache-Control: max-age value
-/// * `compress` - Whether the file should be compressed with gzip
+/// * `compress` - Whether the file should be compressed with br, gzip or deflate, whichever
+///   the client accepts
+/// * `etag` - The file's ETag header value, if known
+/// * `last_modified` - The file's modification time, if known
+/// * `accept_ranges` - Whether byte ranges of the content can be requested
+/// * `download_name` - File name to save the content as, instead of displaying it inline
 pub struct ContentHeaders<'a> {
-    pub content_type: &'a str,
+    pub content_type: Cow<'a, str>,
+    pub charset: Option<String>,
     pub cache_age: u32,
-    pub compress: bool
+    pub compress: bool,
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+    pub accept_ranges: bool,
+    pub download_name: Option<String>,
 }
 
-fn resolve_file_path(path: String) -> Option<String> {
-    let mut validated = String::from(decode(&path).ok()?);
+fn resolve_file_path(path: String, index_files: &[String]) -> Option<String> {
+    let mut validated = path;
     let path = Path::new(&validated);
     if path.is_file() {
         return Some(validated);
-    } else if path.extension() == None && path.with_extension("html").is_file() {
+    } else if path.extension().is_none() && path.with_extension("html").is_file() {
         validated.push_str(".html");
         return Some(validated);
-    } else if path.is_dir() && path.join("index.html").is_file() {
-        match path.join("index.html").to_str() {
-            Some(x) => return Some(String::from(x)),
-            None => return None,
-        }
+    } else if path.is_dir() {
+        let index = index_files
+            .iter()
+            .map(|name| path.join(name))
+            .find(|index| index.is_file())?;
+        return index.to_str().map(String::from);
     }
     None
 }
 
-fn in_serve_folder(root: &str, path: &str) -> bool {
+/// Returns the file system path of a requested path in the served folder.
+/// Returns `None` if the path can't be decoded or would lead outside of the folder.
+fn combined_path(serve_path: &str, content_path: &str) -> Option<String> {
+    let content_path = content_path
+        .strip_prefix(&['/', '\\'][..])
+        .unwrap_or(content_path);
+    // Only the requested path is encoded, the served folder is used as-is.
+    // Decoding happens before the serve folder check, so encoded traversal is caught too.
+    let content_path = decode(content_path).ok()?;
+    if !is_contained_path(&content_path) {
+        return None;
+    }
+    Some(format!("{}/{}", serve_path, content_path))
+}
+
+/// Returns whether the requested path is served as a directory, i.e. it names a directory
+/// in the served folder and no file or `.html` page takes precedence over it.
+///
+/// # Arguments
+///
+/// * `serve_path` - The served folder
+/// * `content_path` - Percent-encoded requested path, relative to `serve_path`
+/// * `follow_symlinks` - Whether the directory may be reached through symbolic links
+pub fn is_directory(serve_path: &str, content_path: &str, follow_symlinks: bool) -> bool {
+    let combined_path = match combined_path(serve_path, content_path) {
+        Some(path) => path,
+        None => return false,
+    };
+    let path = Path::new(&combined_path);
+    let page_first = path.extension().is_none() && path.with_extension("html").is_file();
+    path.is_dir() && !page_first && in_serve_folder(serve_path, &combined_path, follow_symlinks)
+}
+
+/// Returns whether a relative path stays inside the folder it is relative to,
+/// i.e. it has no parent directory, root or prefix components.
+/// Checked before the file system is accessed; symlinks are caught by `in_serve_folder`.
+pub(super) fn is_contained_path(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Returns the path a file uploaded to the given request path is written to.
+/// Returns `None` if the path would lead outside of the upload folder, names a directory,
+/// or its parent directory doesn't exist in the upload folder.
+///
+/// # Arguments
+///
+/// * `upload_path` - The upload folder
+/// * `request_path` - Percent-encoded path of the uploaded file, relative to `upload_path`
+pub fn upload_path(upload_path: &str, request_path: &str) -> Option<String> {
+    let relative_path = request_path
+        .strip_prefix(&['/', '\\'][..])
+        .unwrap_or(request_path);
+    let relative_path = decode(relative_path).ok()?;
+    if relative_path.is_empty()
+        || relative_path.ends_with(&['/', '\\'][..])
+        || !is_contained_path(&relative_path)
+    {
+        return None;
+    }
+    let file_path = format!("{}/{}", upload_path, relative_path);
+    let parent = Path::new(&file_path).parent()?.to_str()?;
+    // Uploads never go through links, even to places inside the folder
+    if Path::new(&file_path).is_dir() || !in_serve_folder(upload_path, parent, false) {
+        return None;
+    }
+    if fs::symlink_metadata(&file_path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        return None;
+    }
+    Some(file_path)
+}
+
+/// Returns whether any part of the path below the root is a symbolic link.
+/// Paths that aren't below the root or can't be inspected are treated as links.
+fn has_symlink(root: &str, path: &str) -> bool {
+    let relative = match Path::new(path).strip_prefix(root) {
+        Ok(value) => value,
+        Err(_) => return true,
+    };
+    let mut current = PathBuf::from(root);
+    for component in relative.components() {
+        current.push(component);
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if !metadata.file_type().is_symlink() => {}
+            _ => return true,
+        }
+    }
+    false
+}
+
+/// Returns whether the path is inside the served folder once symbolic links are resolved.
+///
+/// # Arguments
+///
+/// * `root` - The served folder
+/// * `path` - The path to check, starting with `root`
+/// * `follow_symlinks` - Whether the path may contain symbolic links at all
+pub(super) fn in_serve_folder(root: &str, path: &str, follow_symlinks: bool) -> bool {
+    if !follow_symlinks && has_symlink(root, path) {
+        return false;
+    }
     let root = match Path::new(root).canonicalize() {
         Ok(value) => value,
         Err(_) => {
@@ -122,3 +434,100 @@ fn in_serve_folder(root: &str, path: &str) -> bool {
     }
     true
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// A directory for the test with a served folder and a secret file next to it,
+    /// removed when dropped so it doesn't outlive a failed test
+    struct ServedFolder {
+        root: PathBuf,
+    }
+
+    impl ServedFolder {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("files-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("public/docs")).unwrap();
+            fs::write(root.join("public/docs/page.txt"), "page").unwrap();
+            fs::write(root.join("secret.txt"), "secret").unwrap();
+            ServedFolder { root }
+        }
+    }
+
+    impl Drop for ServedFolder {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn parent_and_absolute_components_are_not_contained() {
+        for path in &["page.txt", "docs/page.txt", "./docs/./page.txt", ""] {
+            assert!(is_contained_path(path), "{}", path);
+        }
+        for path in &[
+            "..",
+            "../secret.txt",
+            "docs/../../secret.txt",
+            "docs/..",
+            "/etc/passwd",
+        ] {
+            assert!(!is_contained_path(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn encoded_traversal_is_rejected_after_decoding() {
+        assert_eq!(
+            combined_path("public", "/docs/my%20page.txt"),
+            Some(String::from("public/docs/my page.txt"))
+        );
+        for path in &[
+            "/../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/..%2Fsecret.txt",
+            "//etc/passwd",
+        ] {
+            assert_eq!(combined_path("public", path), None, "{}", path);
+        }
+        // Not valid UTF-8 once decoded
+        assert_eq!(combined_path("public", "/%ff"), None);
+    }
+
+    #[test]
+    fn links_out_of_the_served_folder_are_not_served() {
+        let folder = ServedFolder::new("links");
+        let public = folder.root.join("public");
+        symlink(folder.root.join("secret.txt"), public.join("secret-link")).unwrap();
+        let public = public.to_str().unwrap();
+        let inside = format!("{}/docs/page.txt", public);
+        assert!(in_serve_folder(public, &inside, true));
+        assert!(in_serve_folder(public, &inside, false));
+        let link = format!("{}/secret-link", public);
+        assert!(!in_serve_folder(public, &link, true));
+        assert!(!in_serve_folder(public, &link, false));
+    }
+
+    #[test]
+    fn uploads_stay_in_existing_folders_of_the_upload_folder() {
+        let folder = ServedFolder::new("uploads");
+        let public = folder.root.join("public");
+        let public = public.to_str().unwrap();
+        assert_eq!(
+            upload_path(public, "/docs/new.txt"),
+            Some(format!("{}/docs/new.txt", public))
+        );
+        for path in &[
+            "/../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/docs",
+            "/docs/",
+            "/missing/new.txt",
+        ] {
+            assert_eq!(upload_path(public, path), None, "{}", path);
+        }
+    }
+}
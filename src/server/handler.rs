@@ -0,0 +1,39 @@
+use super::{Request, Response};
+
+/// Handles requests before the served files are looked up, for adding dynamic routes
+/// to the server. Connections, the thread pool, authentication and logging stay with
+/// the server.
+///
+/// # Example
+/// ```no_run
+/// use http_server::server::{Handler, HttpStatus, Request, Response};
+///
+/// struct Hello;
+///
+/// impl Handler for Hello {
+///     fn handle(&self, request: &Request) -> Option<Response<'static>> {
+///         match request.path {
+///             "/hello" => Some(Response::new(HttpStatus::Ok, b"Hello!".to_vec())),
+///             _ => None,
+///         }
+///     }
+/// }
+///
+/// let config = http_server::ServerBuilder::new("public").handler(Hello).build();
+/// http_server::run(config).unwrap();
+/// ```
+pub trait Handler: Send + Sync {
+    /// Returns the response to the request, or `None` to serve the request from the
+    /// files as usual. The default headers, e.g. `Date` and `Content-Length`, are added
+    /// to the response unless it sets them itself.
+    ///
+    /// # Arguments
+    /// * `request` - The request, with its path as sent by the client
+    fn handle(&self, request: &Request) -> Option<Response<'static>>;
+}
+
+/// A function that changes a response before it is sent, e.g. to add headers
+///
+/// Transformers run after the response is complete, including compression, so a
+/// transformer that replaces the payload must update `Content-Length` itself.
+pub type Transformer = Box<dyn Fn(&Request, &mut Response) + Send + Sync>;
@@ -0,0 +1,334 @@
+use super::files::{in_serve_folder, is_contained_path};
+use chrono::{DateTime, Local};
+use std::fs;
+use std::path::Path;
+use urlencoding::{decode, encode};
+
+/// Page of the plain directory listing style, see `directory_listing` for the placeholders
+pub const PLAIN_TEMPLATE: &str = "<!DOCTYPE html>
+<html lang=\"en\">
+  <head>
+    <meta charset=\"UTF-8\">
+    <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">
+
+    <title>Index of {title}</title>
+  </head>
+
+  <body>
+    <h1>Index of {title}</h1>
+    <table>
+      <tr><th>Name</th><th>Size</th><th>Modified</th></tr>
+{rows}    </table>
+  </body>
+</html>
+";
+
+/// Page of the styled directory listing style, with columns that sort when clicked
+pub const STYLED_TEMPLATE: &str = "<!DOCTYPE html>
+<html lang=\"en\">
+  <head>
+    <meta charset=\"UTF-8\">
+    <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">
+
+    <title>Index of {title}</title>
+    <style>
+      body { font-family: system-ui, sans-serif; margin: 2em auto; max-width: 60em; padding: 0 1em; color: #222; }
+      h1 { font-size: 1.4em; font-weight: normal; }
+      table { border-collapse: collapse; width: 100%; }
+      th { cursor: pointer; text-align: left; user-select: none; border-bottom: 2px solid #ccc; }
+      th, td { padding: 0.4em 0.8em; }
+      td:nth-child(2), td:nth-child(3) { white-space: nowrap; color: #666; }
+      tr:nth-child(even) td { background: #f6f6f6; }
+      a { color: #0550ae; text-decoration: none; }
+      a:hover { text-decoration: underline; }
+    </style>
+  </head>
+
+  <body>
+    <h1>Index of {title}</h1>
+    <table>
+      <thead>
+        <tr><th>Name</th><th>Size</th><th>Modified</th></tr>
+      </thead>
+      <tbody>
+{rows}      </tbody>
+    </table>
+    <script>
+      document.querySelectorAll(\"th\").forEach(function (header, column) {
+        var ascending = true;
+        header.addEventListener(\"click\", function () {
+          var body = document.querySelector(\"tbody\");
+          var rows = Array.from(body.rows).filter(function (row) { return !row.dataset.parent; });
+          var key = function (row) {
+            var cell = row.cells[column];
+            return cell.dataset.sort !== undefined ? Number(cell.dataset.sort) : cell.textContent;
+          };
+          rows.sort(function (a, b) {
+            var x = key(a), y = key(b);
+            var order = x < y ? -1 : x > y ? 1 : 0;
+            return ascending ? order : -order;
+          });
+          ascending = !ascending;
+          rows.forEach(function (row) { body.appendChild(row); });
+        });
+      });
+    </script>
+  </body>
+</html>
+";
+
+/// Size units of the human-readable file sizes, each 1024 times the previous one
+const SIZE_UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+/// A single entry in a directory listing
+struct ListingEntry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<DateTime<Local>>,
+}
+
+/// Returns an HTML page listing the contents of a directory in the served folder.
+/// Returns `None` if the path isn't a directory inside the served folder.
+///
+/// The page is made from `template` by replacing `{title}` with the escaped path of the
+/// directory and `{rows}` with one table row per entry, each with a name, size and
+/// modification time cell.
+///
+/// # Arguments
+///
+/// * `serve_path` - The served folder
+/// * `request_path` - The requested path, as sent by the client without the URL prefix
+/// * `url_prefix` - The path prefix the server is deployed under, prepended to the links
+/// * `follow_symlinks` - Whether the directory may be reached through symbolic links, and
+///   whether the links in it are listed
+/// * `template` - The page to fill in, e.g. `PLAIN_TEMPLATE`
+pub fn directory_listing(
+    serve_path: &str,
+    request_path: &str,
+    url_prefix: &str,
+    follow_symlinks: bool,
+    template: &str,
+) -> Option<String> {
+    let relative_path = request_path
+        .strip_prefix(&['/', '\\'][..])
+        .unwrap_or(request_path);
+    let relative_path = decode(relative_path).ok()?;
+    if !is_contained_path(&relative_path) {
+        return None;
+    }
+    let dir_path = format!("{}/{}", serve_path, relative_path);
+    if !Path::new(&dir_path).is_dir() || !in_serve_folder(serve_path, &dir_path, follow_symlinks) {
+        return None;
+    }
+
+    let mut entries = Vec::<ListingEntry>::new();
+    for entry in fs::read_dir(&dir_path).ok()? {
+        let entry = entry.ok()?;
+        // Links are listed as what they point to, and left out if they can't be followed
+        let metadata = match entry.file_type().ok()? {
+            kind if kind.is_symlink() && !follow_symlinks => continue,
+            kind if kind.is_symlink() => match fs::metadata(entry.path()) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            },
+            _ => entry.metadata().ok()?,
+        };
+        entries.push(ListingEntry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(DateTime::from),
+        });
+    }
+    // Directories first, then files, both alphabetically
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+    let base = if request_path.ends_with('/') {
+        format!("{}{}", url_prefix, request_path)
+    } else {
+        format!("{}{}/", url_prefix, request_path)
+    };
+    let title = escape_html(&decode(&base).ok()?);
+
+    let mut rows = String::new();
+    // The root of the served folder has no parent, even under a URL prefix
+    let unprefixed = &base[url_prefix.len()..];
+    if let Some(parent_end) = unprefixed.trim_end_matches('/').rfind('/') {
+        rows.push_str(&format!(
+            "      <tr data-parent=\"1\"><td><a href=\"{}\">../</a></td><td></td><td></td></tr>\n",
+            escape_html(&base[..=url_prefix.len() + parent_end])
+        ));
+    }
+    for entry in &entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        let (size, sort_size) = if entry.is_dir {
+            (String::from("-"), 0)
+        } else {
+            (human_size(entry.size), entry.size)
+        };
+        let modified = match entry.modified {
+            Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+            None => String::from("-"),
+        };
+        rows.push_str(&format!(
+            "      <tr><td><a href=\"{}{}{}\">{}{}</a></td><td data-sort=\"{}\">{}</td><td>{}</td></tr>\n",
+            escape_html(&base),
+            encode(&entry.name),
+            suffix,
+            escape_html(&entry.name),
+            suffix,
+            sort_size,
+            size,
+            modified
+        ));
+    }
+
+    Some(fill_template(template, &title, &rows))
+}
+
+/// Returns the template with its `{title}` and `{rows}` placeholders replaced.
+/// The replacements are inserted as-is, so placeholders inside them are left alone.
+fn fill_template(template: &str, title: &str, rows: &str) -> String {
+    let mut page = String::with_capacity(template.len() + rows.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        page.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("{title}") {
+            page.push_str(title);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{rows}") {
+            page.push_str(rows);
+            rest = after;
+        } else {
+            page.push('{');
+            rest = &rest[1..];
+        }
+    }
+    page.push_str(rest);
+    page
+}
+
+/// Returns a file size in the largest unit that keeps it at least one, e.g. `1.5 KiB`.
+fn human_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < SIZE_UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, SIZE_UNITS[0])
+    } else {
+        format!("{:.1} {}", size, SIZE_UNITS[unit])
+    }
+}
+
+/// Escapes the characters that have a special meaning in HTML.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+
+    /// Returns an empty directory for the test, with a file, a folder and links to both.
+    fn linked_folder(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("listing-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("folder")).unwrap();
+        fs::write(root.join("file.txt"), "text").unwrap();
+        symlink(root.join("folder"), root.join("folder-link")).unwrap();
+        symlink(root.join("file.txt"), root.join("file-link")).unwrap();
+        symlink(root.join("missing"), root.join("broken-link")).unwrap();
+        root
+    }
+
+    fn listing(root: &Path, follow_symlinks: bool) -> String {
+        let root = root.to_str().unwrap();
+        directory_listing(root, "/", "", follow_symlinks, PLAIN_TEMPLATE).unwrap()
+    }
+
+    #[test]
+    fn followed_links_are_listed_as_their_targets() {
+        let root = linked_folder("follow");
+        let page = listing(&root, true);
+        assert!(page.contains(">folder-link/</a>"));
+        assert!(page.contains(">file-link</a>"));
+        assert!(!page.contains("broken-link"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn links_are_hidden_when_not_followed() {
+        let root = linked_folder("no-follow");
+        let page = listing(&root, false);
+        assert!(page.contains(">folder/</a>"));
+        assert!(page.contains(">file.txt</a>"));
+        assert!(!page.contains("-link"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn nested_directory_lists_parent_then_folders_then_files() {
+        let root = std::env::temp_dir().join(format!("listing-{}-nested", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let docs = root.join("docs");
+        fs::create_dir_all(docs.join("zeta")).unwrap();
+        fs::create_dir_all(docs.join("alpha")).unwrap();
+        for file in &["b.txt", "a.txt", "my file.txt"] {
+            fs::write(docs.join(file), "text").unwrap();
+        }
+        let page =
+            directory_listing(root.to_str().unwrap(), "/docs", "", false, PLAIN_TEMPLATE).unwrap();
+        let links: Vec<&str> = page
+            .split("<a href=\"")
+            .skip(1)
+            .map(|rest| &rest[..rest.find('"').unwrap()])
+            .collect();
+        assert_eq!(
+            links,
+            [
+                "/",
+                "/docs/alpha/",
+                "/docs/zeta/",
+                "/docs/a.txt",
+                "/docs/b.txt",
+                "/docs/my%20file.txt"
+            ]
+        );
+        assert!(page.contains("<a href=\"/\">../</a>"));
+        assert!(page.contains(">my file.txt</a>"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn file_names_are_escaped() {
+        let root = std::env::temp_dir().join(format!("listing-{}-escape", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("<script>.txt"), "text").unwrap();
+        for template in &[PLAIN_TEMPLATE, STYLED_TEMPLATE] {
+            let page = directory_listing(root.to_str().unwrap(), "/", "", false, template).unwrap();
+            assert!(page.contains(">&lt;script&gt;.txt</a>"));
+            assert!(page.contains("href=\"/%3Cscript%3E.txt\""));
+            assert!(!page.contains("<script>.txt"));
+        }
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Lowest HTTP status code
+const MIN_STATUS: u16 = 100;
+
+/// Highest HTTP status code
+const MAX_STATUS: u16 = 599;
+
+/// Counters describing the server's traffic, shared between all connections
+///
+/// * `requests` - The number of requests received
+/// * `responses` - The number of responses sent, indexed by status code from `MIN_STATUS`
+/// * `bytes` - The number of body bytes sent
+/// * `active` - The number of connections currently being handled
+pub struct Metrics {
+    requests: AtomicU64,
+    responses: Vec<AtomicU64>,
+    bytes: AtomicU64,
+    active: AtomicUsize,
+}
+
+/// Marks a connection as active for as long as it is alive
+pub struct ActiveConnection<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Metrics {
+    /// Returns a new `Metrics` instance with all counters at zero.
+    pub fn new() -> Self {
+        Metrics {
+            requests: AtomicU64::new(0),
+            responses: (MIN_STATUS..=MAX_STATUS)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            bytes: AtomicU64::new(0),
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    /// Counts a received request.
+    pub fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a sent response.
+    ///
+    /// # Arguments
+    /// * `status` - The response's HTTP status code
+    /// * `bytes` - The number of body bytes sent
+    pub fn record_response(&self, status: u16, bytes: u64) {
+        if let Some(counter) = self.counter(status) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Returns the number of responses sent with the given status code.
+    pub fn responses(&self, status: u16) -> u64 {
+        self.counter(status)
+            .map_or(0, |counter| counter.load(Ordering::Relaxed))
+    }
+
+    /// Returns the number of responses sent with a status code in the given class,
+    /// e.g. `4` for 4xx.
+    pub fn responses_in_class(&self, class: u16) -> u64 {
+        (class * 100..class * 100 + 100)
+            .map(|status| self.responses(status))
+            .sum()
+    }
+
+    fn counter(&self, status: u16) -> Option<&AtomicU64> {
+        let index = status.checked_sub(MIN_STATUS)?;
+        self.responses.get(usize::from(index))
+    }
+
+    /// Returns the status codes that have been sent, with the number of responses for each.
+    fn sent_statuses(&self) -> impl Iterator<Item = (u16, u64)> + '_ {
+        (MIN_STATUS..=MAX_STATUS)
+            .map(move |status| (status, self.responses(status)))
+            .filter(|(_, count)| *count > 0)
+    }
+
+    /// Returns a one-line summary of the counters, for logging.
+    pub fn summary(&self) -> String {
+        let statuses: Vec<String> = self
+            .sent_statuses()
+            .map(|(status, count)| format!("{}: {}", status, count))
+            .collect();
+        format!(
+            "Served {} requests and {} bytes, responses by status: {}",
+            self.requests.load(Ordering::Relaxed),
+            self.bytes.load(Ordering::Relaxed),
+            if statuses.is_empty() {
+                String::from("none")
+            } else {
+                statuses.join(", ")
+            }
+        )
+    }
+
+    /// Marks a connection as active until the returned value is dropped.
+    pub fn track_connection(&self) -> ActiveConnection<'_> {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        ActiveConnection { metrics: self }
+    }
+
+    /// Returns the counters as plain text, one `name value` pair per line.
+    pub fn render(&self) -> String {
+        let mut text = format!(
+            "http_requests_total {}\n",
+            self.requests.load(Ordering::Relaxed)
+        );
+        for class in 1..=5 {
+            text.push_str(&format!(
+                "http_responses_total{{class=\"{}xx\"}} {}\n",
+                class,
+                self.responses_in_class(class)
+            ));
+        }
+        for (status, count) in self.sent_statuses() {
+            text.push_str(&format!(
+                "http_responses_total{{status=\"{}\"}} {}\n",
+                status, count
+            ));
+        }
+        text.push_str(&format!(
+            "http_response_bytes_total {}\n",
+            self.bytes.load(Ordering::Relaxed)
+        ));
+        text.push_str(&format!(
+            "http_active_connections {}\n",
+            self.active.load(Ordering::Relaxed)
+        ));
+        text
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for ActiveConnection<'_> {
+    fn drop(&mut self) {
+        self.metrics.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_are_counted_by_status_and_class() {
+        let metrics = Metrics::new();
+        metrics.record_request();
+        metrics.record_request();
+        metrics.record_response(200, 10);
+        metrics.record_response(204, 0);
+        metrics.record_response(404, 5);
+        // Codes outside the valid range still count their bytes
+        metrics.record_response(999, 1);
+        assert_eq!(metrics.responses(200), 1);
+        assert_eq!(metrics.responses(999), 0);
+        assert_eq!(metrics.responses_in_class(2), 2);
+        assert_eq!(metrics.responses_in_class(4), 1);
+        assert_eq!(
+            metrics.summary(),
+            "Served 2 requests and 16 bytes, responses by status: 200: 1, 204: 1, 404: 1"
+        );
+    }
+
+    #[test]
+    fn active_connections_are_counted_until_dropped() {
+        let metrics = Metrics::new();
+        let first = metrics.track_connection();
+        let second = metrics.track_connection();
+        assert!(metrics.render().contains("http_active_connections 2\n"));
+        drop(first);
+        drop(second);
+        let text = metrics.render();
+        assert!(text.contains("http_active_connections 0\n"));
+        assert!(text.contains("http_requests_total 0\n"));
+        assert!(text.contains("http_responses_total{class=\"5xx\"} 0\n"));
+        assert_eq!(
+            Metrics::new().summary(),
+            "Served 0 requests and 0 bytes, responses by status: none"
+        );
+    }
+}
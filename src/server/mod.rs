@@ -1,10 +1,29 @@
+mod access_log;
+mod auth;
+mod cache;
 mod files;
+mod handler;
+mod listing;
+mod metrics;
+mod redirects;
 mod request;
 mod response;
 mod threadpool;
+mod tls;
 
+pub use access_log::AccessLog;
+pub use auth::BasicAuth;
+pub use cache::FileCache;
 pub use files::*;
+pub use handler::{Handler, Transformer};
+pub use listing::{directory_listing, escape_html, PLAIN_TEMPLATE, STYLED_TEMPLATE};
+pub use metrics::Metrics;
+pub use redirects::{Redirect, Redirects};
+pub use request::ByteRange;
+pub use request::Method;
 pub use request::Request;
 pub use response::Response;
 pub use response::HttpStatus;
+pub use response::{compress, CachePolicy, ResponseSettings, SERVER_NAME};
 pub use threadpool::ThreadPool;
+pub use tls::{tls_config, Connection, TlsStream};
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+
+/// Redirect rules read from a file, by the request path they apply to
+///
+/// Each non-empty line of the file holds one rule: the request path, the target and
+/// optionally `301` for a permanent or `302` for a temporary redirect, e.g.
+/// `/old-page /new-page 302`. Redirects are permanent by default.
+/// Lines starting with `#` are comments.
+pub struct Redirects {
+    rules: HashMap<String, Redirect>,
+}
+
+/// Where a redirected request is sent
+///
+/// * `target` - Value of the `Location` header, a path or an absolute URL
+/// * `permanent` - Whether the redirect is `301 Moved Permanently` instead of `302 Found`
+#[derive(Clone)]
+pub struct Redirect {
+    pub target: String,
+    pub permanent: bool,
+}
+
+impl Redirects {
+    /// Reads the redirect rules from the file at the given path.
+    /// Returns `io::Error` if the file could not be read or contains an invalid rule.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the rules file
+    pub fn load(path: &str) -> Result<Self> {
+        let mut rules = HashMap::new();
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: {}", number + 1, reason),
+                )
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let permanent = match fields.get(2).copied() {
+                None | Some("301") => true,
+                Some("302") => false,
+                Some(_) => return Err(invalid("the status must be 301 or 302")),
+            };
+            match fields[..] {
+                [from, target, ..] if fields.len() <= 3 && from.starts_with('/') => {
+                    let redirect = Redirect {
+                        target: target.to_string(),
+                        permanent,
+                    };
+                    rules.insert(from.to_string(), redirect);
+                }
+                _ => {
+                    return Err(invalid(
+                        "expected a path starting with /, a target and an optional status",
+                    ))
+                }
+            }
+        }
+        Ok(Redirects { rules })
+    }
+
+    /// Returns the redirect for the given request path, if a rule matches it.
+    pub fn get(&self, path: &str) -> Option<&Redirect> {
+        self.rules.get(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads redirect rules from a file with the given contents.
+    fn load(name: &str, contents: &str) -> Result<Redirects> {
+        let path = std::env::temp_dir().join(format!("redirects-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        let redirects = Redirects::load(path.to_str().unwrap());
+        fs::remove_file(path).unwrap();
+        redirects
+    }
+
+    #[test]
+    fn rules_are_read_with_their_status() {
+        let redirects = load(
+            "valid",
+            "# Moved pages\n\n/old /new\n  /blog https://blog.example.com 302  \n/kept /here 301\n",
+        )
+        .unwrap();
+        let old = redirects.get("/old").unwrap();
+        assert_eq!((old.target.as_str(), old.permanent), ("/new", true));
+        let blog = redirects.get("/blog").unwrap();
+        assert_eq!(
+            (blog.target.as_str(), blog.permanent),
+            ("https://blog.example.com", false)
+        );
+        assert!(redirects.get("/kept").unwrap().permanent);
+        assert!(redirects.get("/new").is_none());
+    }
+
+    #[test]
+    fn invalid_rules_name_their_line() {
+        for (name, contents) in &[
+            ("status", "/old /new\n/other /place 307\n"),
+            ("relative", "/old /new\nold /new\n"),
+            ("target", "/old /new\n/old\n"),
+            ("extra", "/old /new\n/old /new 301 extra\n"),
+        ] {
+            let err = load(name, contents).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", name);
+            assert!(err.to_string().starts_with("line 2:"), "{}: {}", name, err);
+        }
+    }
+}
@@ -1,45 +1,559 @@
+use chrono::DateTime;
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The method of an HTTP request. Methods are case-sensitive, so e.g. `get` is `Other`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Other(String),
+}
+
+impl Method {
+    /// Returns the method named by the given request line token.
+    pub fn parse(token: &str) -> Method {
+        match token {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "OPTIONS" => Method::Options,
+            other => Method::Other(other.to_string()),
+        }
+    }
+
+    /// Returns the method's name as it appears in the request line, e.g. `GET`.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+            Method::Other(name) => name,
+        }
+    }
+}
 
 /// A parsed HTTP Request
 ///
 /// * `method` - The HTTP method used in the request
-/// * `path` - The requested resource path
+/// * `path` - The requested resource path, without the query string
+/// * `query` - The query string following the `?` in the request target, if any
+/// * `authority` - The host and port of an absolute-form target, e.g. `http://host/path`
 /// * `protocol` - The used HTTP protocol version
-/// * `headers` - A key-value table containing the request's headers
+/// * `headers` - A key-value table containing the request's headers, with lowercase names.
+///   Use `get_header` for lookups
 pub struct Request<'a> {
-    pub method: &'a str,
+    pub method: Method,
     pub path: &'a str,
+    pub query: Option<&'a str>,
+    pub authority: Option<&'a str>,
     pub protocol: &'a str,
-    pub headers: HashMap<&'a str, &'a str>
+    pub headers: HashMap<String, &'a str>
 }
 
 impl Request<'_> {
     /// Returns a request object for the given HTTP/1.1 request string.
-    /// Returns `None` if the string doesn't contain a valid request.
+    /// Returns `None` if the string doesn't contain a valid request, e.g. if the request
+    /// line doesn't consist of exactly a method, a target and a protocol version, or the
+    /// target isn't a path, an absolute `http` or `https` URL or `*`, or if a header line
+    /// isn't a field name directly followed by a colon.
     ///
     /// # Arguments
     /// * `req` - The request string to parse
-    pub fn parse(req: &str) -> Option<Request> {
-        let request_line: Vec<&str> = req.splitn(3, ' ').collect();
-
-        if request_line.len() == 3 {
-            let protocol: Vec<&str> = request_line[2].split("\r\n").collect();
-            let mut headers: HashMap<&str, &str> = HashMap::new();
-            for line in &protocol[1..] {
-                let pair: Vec<&str> = line.splitn(2, ":").collect();
-                if pair.len() == 2 {
-                    headers.insert(pair[0].trim(), pair[1].trim());
-                }
+    pub fn parse(req: &str) -> Option<Request<'_>> {
+        // Empty lines before the request line are ignored, and lines may end with a lone
+        // `\n` instead of `\r\n`, as recommended by RFC 7230 for robustness
+        let req = req.trim_start_matches(&['\r', '\n'][..]);
+        let (request_line, header_lines) = req.split_once('\n')?;
+        let request_line = request_line.strip_suffix('\r').unwrap_or(request_line);
+        let mut tokens = request_line.split_whitespace();
+        let (method, target, protocol) = (tokens.next()?, tokens.next()?, tokens.next()?);
+        if tokens.next().is_some() || !protocol.starts_with("HTTP/") {
+            return None;
+        }
+
+        let mut headers: HashMap<String, &str> = HashMap::new();
+        for line in header_lines.split('\n') {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if line.is_empty() {
+                break;
             }
-            if protocol.len() > 1 {
-                return Some(Request {
-                    method: request_line[0],
-                    path: request_line[1],
-                    protocol: protocol[0],
-                    headers
-                });
+            // Whitespace around a field name could make proxies read it differently,
+            // so RFC 7230 requires rejecting it
+            let (name, value) = line.split_once(':')?;
+            if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace()) {
+                return None;
             }
+            headers.insert(name.to_ascii_lowercase(), value.trim());
+        }
+        let (authority, target) = split_absolute_target(target)?;
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (target, None),
+        };
+        Some(Request {
+            method: Method::parse(method),
+            path,
+            query,
+            authority,
+            protocol,
+            headers
+        })
+    }
+
+    /// Returns the name of the request's method, e.g. `GET`.
+    pub fn method_str(&self) -> &str {
+        self.method.as_str()
+    }
+
+    /// Returns the value of the given header. Header names are case-insensitive.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the header
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).copied()
+    }
+
+    /// Returns whether the request's `Connection` header lists the given option,
+    /// e.g. `close` or `keep-alive`. Options are case-insensitive.
+    ///
+    /// # Arguments
+    /// * `option` - The connection option to look for
+    pub fn has_connection_option(&self, option: &str) -> bool {
+        self.get_header("Connection").is_some_and(|value| {
+            value
+                .split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case(option))
+        })
+    }
+
+    /// Returns the host name from the request's `Host` header, without the port.
+    /// The authority of an absolute-form target replaces the header, as required by RFC 7230.
+    /// Returns `None` if neither is present.
+    pub fn host_name(&self) -> Option<&str> {
+        let host = match self.authority {
+            // User information is deprecated in HTTP URLs and never part of the host
+            Some(authority) => authority.rsplit('@').next().unwrap_or(authority),
+            None => self.get_header("Host")?,
+        };
+        // IPv6 addresses are enclosed in brackets, since they contain colons themselves
+        if host.starts_with('[') {
+            return Some(host.find(']').map_or(host, |end| &host[..=end]));
+        }
+        match host.rsplit_once(':') {
+            Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => Some(name),
+            _ => Some(host),
         }
-        None
+    }
+
+    /// Returns the key-value pairs of the request's query string.
+    /// Keys without a value are mapped to an empty string. The pairs are not percent-decoded.
+    pub fn query_params(&self) -> HashMap<&str, &str> {
+        let mut params = HashMap::new();
+        for pair in self.query.unwrap_or("").split('&') {
+            if pair.is_empty() {
+                continue;
+            }
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            params.insert(key, value);
+        }
+        params
+    }
+
+    /// Returns whether the client accepts the given content encoding,
+    /// according to the request's `Accept-Encoding` header.
+    /// Encodings listed with a quality value of zero are considered unacceptable.
+    ///
+    /// # Arguments
+    /// * `encoding` - The content encoding to check for, e.g. `gzip`
+    pub fn accepts_encoding(&self, encoding: &str) -> bool {
+        let encodings = match self.get_header("Accept-Encoding") {
+            Some(x) => x,
+            None => return false,
+        };
+        let mut wildcard = false;
+        for entry in encodings.split(',') {
+            let mut params = entry.split(';');
+            let name = params.next().unwrap_or("").trim();
+            let accepted = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .all(|q| q.trim().parse::<f32>().is_ok_and(|q| q > 0.0));
+            if name.eq_ignore_ascii_case(encoding) {
+                return accepted;
+            } else if name == "*" {
+                wildcard = accepted;
+            }
+        }
+        wildcard
+    }
+
+    /// Returns whether the client prefers a JSON response over HTML, according to the
+    /// request's `Accept` header. HTML is preferred when both are equally acceptable.
+    pub fn prefers_json(&self) -> bool {
+        match self.get_header("Accept") {
+            Some(accept) => {
+                media_quality(accept, "application/json") > media_quality(accept, "text/html")
+            }
+            None => false,
+        }
+    }
+
+    /// Returns whether the given entity tag matches the request's `If-None-Match` header,
+    /// using weak comparison. Returns `None` if the header is missing.
+    ///
+    /// # Arguments
+    /// * `etag` - The current entity tag of the requested resource
+    pub fn if_none_match(&self, etag: &str) -> Option<bool> {
+        let tags = self.get_header("If-None-Match")?;
+        let etag = etag.trim_start_matches("W/");
+        Some(
+            tags.split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag),
+        )
+    }
+
+    /// Returns the date from the request's `If-Modified-Since` header.
+    /// Returns `None` if the header is missing or isn't a valid date.
+    pub fn if_modified_since(&self) -> Option<SystemTime> {
+        let date = DateTime::parse_from_rfc2822(self.get_header("If-Modified-Since")?).ok()?;
+        let seconds = u64::try_from(date.timestamp()).ok()?;
+        Some(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    /// Returns whether the request's `If-Range` header still matches the resource,
+    /// so that its `Range` header applies. Returns `true` if the header is missing.
+    /// Entity tags use strong comparison, and dates must equal the modification time.
+    ///
+    /// # Arguments
+    /// * `etag` - The current entity tag of the requested resource, if any
+    /// * `modified` - The current modification time of the requested resource, if any
+    pub fn if_range_matches(&self, etag: Option<&str>, modified: Option<SystemTime>) -> bool {
+        let condition = match self.get_header("If-Range") {
+            Some(value) => value.trim(),
+            None => return true,
+        };
+        if condition.starts_with('"') || condition.starts_with("W/") {
+            // Weak tags never match, since the bytes of the resource might differ
+            return !condition.starts_with("W/") && etag == Some(condition);
+        }
+        let date = match DateTime::parse_from_rfc2822(condition) {
+            Ok(date) => date,
+            Err(_) => return false,
+        };
+        // Header dates only have a resolution of one second
+        let modified = modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_secs());
+        modified.is_some() && u64::try_from(date.timestamp()).ok() == modified
+    }
+
+    /// Returns the byte range requested with the `Range` header.
+    /// Returns `None` if the header is missing, malformed, or requests multiple ranges,
+    /// in which case the whole resource should be sent.
+    pub fn byte_range(&self) -> Option<ByteRange> {
+        let spec = self.get_header("Range")?.trim().strip_prefix("bytes=")?;
+        if spec.contains(',') {
+            return None;
+        }
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        let start = if start.is_empty() {
+            None
+        } else {
+            Some(start.parse::<u64>().ok()?)
+        };
+        let end = if end.is_empty() {
+            None
+        } else {
+            Some(end.parse::<u64>().ok()?)
+        };
+        match (start, end) {
+            (None, None) => None,
+            (Some(start), Some(end)) if start > end => None,
+            _ => Some(ByteRange { start, end }),
+        }
+    }
+}
+
+/// A single byte range from a `Range: bytes=...` header.
+///
+/// * `start` - The first requested byte, or `None` for a suffix range
+/// * `end` - The last requested byte (inclusive), or the suffix length if `start` is `None`.
+///   `None` means the range extends to the end of the resource
+pub struct ByteRange {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+impl ByteRange {
+    /// Returns the inclusive start and end offsets of the range within a resource of
+    /// `total` bytes. Returns `None` if the range can't be satisfied.
+    ///
+    /// # Arguments
+    /// * `total` - The size of the resource in bytes
+    pub fn resolve(&self, total: u64) -> Option<(u64, u64)> {
+        if total == 0 {
+            return None;
+        }
+        match (self.start, self.end) {
+            (Some(start), _) if start >= total => None,
+            (Some(start), Some(end)) => Some((start, end.min(total - 1))),
+            (Some(start), None) => Some((start, total - 1)),
+            (None, Some(0)) => None,
+            (None, Some(suffix)) => Some((total.saturating_sub(suffix), total - 1)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Splits an absolute-form request target, e.g. `http://example.com/path`, into its
+/// authority and the path with its query. Paths and `*` are returned as they are,
+/// without an authority. Returns `None` for any other kind of target.
+fn split_absolute_target(target: &str) -> Option<(Option<&str>, &str)> {
+    if target.starts_with('/') || target == "*" {
+        return Some((None, target));
+    }
+    let (scheme, rest) = target.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+    let end = rest.find(&['/', '?'][..]).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(end);
+    if authority.is_empty() {
+        return None;
+    }
+    // An empty path is the root
+    Some((Some(authority), if path.is_empty() { "/" } else { path }))
+}
+
+/// Returns the quality value an `Accept` header gives the media type, or zero if the
+/// header doesn't accept it. The most specific matching entry applies, e.g. `text/html`
+/// over `text/*` over `*/*`.
+///
+/// # Arguments
+/// * `accept` - Value of the `Accept` header
+/// * `media_type` - The media type to look up, e.g. `text/html`
+fn media_quality(accept: &str, media_type: &str) -> f32 {
+    let main_type = media_type.split('/').next().unwrap_or("");
+    let mut best = (0, 0.0);
+    for entry in accept.split(',') {
+        let mut params = entry.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let specificity = if name.eq_ignore_ascii_case(media_type) {
+            3
+        } else if name
+            .strip_suffix("/*")
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(main_type))
+        {
+            2
+        } else if name == "*/*" {
+            1
+        } else {
+            continue;
+        };
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if specificity > best.0 {
+            best = (specificity, quality);
+        }
+    }
+    best.1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_line_and_headers_are_parsed() {
+        let request = Request::parse(
+            "GET /docs/page.html?lang=en&print HTTP/1.1\r\nHost: example.com\r\n\
+             Accept-Encoding: gzip, br\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(request.path, "/docs/page.html");
+        assert_eq!(request.query, Some("lang=en&print"));
+        assert_eq!(request.protocol, "HTTP/1.1");
+        assert_eq!(request.authority, None);
+        assert_eq!(request.get_header("Host"), Some("example.com"));
+        let params = request.query_params();
+        assert_eq!(params.get("lang"), Some(&"en"));
+        assert_eq!(params.get("print"), Some(&""));
+    }
+
+    #[test]
+    fn header_names_are_case_insensitive() {
+        let request =
+            Request::parse("GET / HTTP/1.1\r\ncontent-TYPE:  text/plain \r\n\r\n").unwrap();
+        assert_eq!(request.get_header("Content-Type"), Some("text/plain"));
+        assert_eq!(request.get_header("CONTENT-type"), Some("text/plain"));
+        assert_eq!(request.get_header("Content-Length"), None);
+    }
+
+    #[test]
+    fn methods_are_case_sensitive() {
+        assert_eq!(Method::parse("HEAD"), Method::Head);
+        assert_eq!(Method::parse("get"), Method::Other(String::from("get")));
+        let request = Request::parse("BREW /pot HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.method_str(), "BREW");
+    }
+
+    #[test]
+    fn malformed_request_lines_are_rejected() {
+        for request in &[
+            "",
+            "GET\r\n\r\n",
+            "GET /\r\n\r\n",
+            "GET / HTTP/1.1 extra\r\n\r\n",
+            "GET / FTP/1.0\r\n\r\n",
+            "GET relative/path HTTP/1.1\r\n\r\n",
+            "GET / HTTP/1.1",
+        ] {
+            assert!(Request::parse(request).is_none(), "{:?}", request);
+        }
+    }
+
+    #[test]
+    fn malformed_header_lines_are_rejected() {
+        for request in &[
+            "GET / HTTP/1.1\r\nHost example.com\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost : example.com\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost\t: example.com\r\n\r\n",
+            "GET / HTTP/1.1\r\n Host: example.com\r\n\r\n",
+            "GET / HTTP/1.1\r\n: example.com\r\n\r\n",
+        ] {
+            assert!(Request::parse(request).is_none(), "{:?}", request);
+        }
+        // Whitespace after the colon is optional
+        let request = Request::parse("GET / HTTP/1.1\r\nHost:example.com\r\n\r\n").unwrap();
+        assert_eq!(request.get_header("Host"), Some("example.com"));
+    }
+
+    #[test]
+    fn lone_line_feeds_and_leading_blank_lines_are_accepted() {
+        for raw in &[
+            "GET /page HTTP/1.1\nHost: example.com\n\n",
+            "\r\n\nGET /page HTTP/1.1\r\nHost: example.com\r\n\r\n",
+            "GET /page HTTP/1.1\r\nHost: example.com\n\r\n",
+        ] {
+            let request = Request::parse(raw).unwrap();
+            assert_eq!(request.path, "/page", "{:?}", raw);
+            assert_eq!(request.get_header("Host"), Some("example.com"), "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn absolute_form_targets_are_split_into_authority_and_path() {
+        let request = Request::parse("GET http://example.com:8080/a?b=c HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.authority, Some("example.com:8080"));
+        assert_eq!(request.path, "/a");
+        assert_eq!(request.query, Some("b=c"));
+        let request = Request::parse("GET HTTPS://example.com HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.path, "/");
+        let request = Request::parse("OPTIONS * HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!((request.authority, request.path), (None, "*"));
+        for target in &["ftp://example.com/", "http:///path", "example.com:443"] {
+            let raw = format!("GET {} HTTP/1.1\r\n\r\n", target);
+            assert!(Request::parse(&raw).is_none(), "{}", target);
+        }
+    }
+
+    #[test]
+    fn absolute_form_authority_replaces_the_host_header() {
+        let request =
+            Request::parse("GET http://user@target.com/ HTTP/1.1\r\nHost: other.com\r\n\r\n")
+                .unwrap();
+        assert_eq!(request.host_name(), Some("target.com"));
+        let request = Request::parse("GET / HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n").unwrap();
+        assert_eq!(request.host_name(), Some("[::1]"));
+        let request = Request::parse("GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.host_name(), None);
+    }
+
+    /// Returns a request with the given `Accept-Encoding` header.
+    fn accepting(encodings: &str) -> String {
+        format!("GET / HTTP/1.1\r\nAccept-Encoding: {}\r\n\r\n", encodings)
+    }
+
+    #[test]
+    fn encodings_with_a_zero_quality_are_not_accepted() {
+        let raw = accepting("gzip;q=0, br; q=0.5, deflate;q=0.000");
+        let request = Request::parse(&raw).unwrap();
+        assert!(!request.accepts_encoding("gzip"));
+        assert!(request.accepts_encoding("br"));
+        assert!(!request.accepts_encoding("deflate"));
+        // Unparsable quality values don't count as acceptance
+        let raw = accepting("gzip;q=high");
+        assert!(!Request::parse(&raw).unwrap().accepts_encoding("gzip"));
+    }
+
+    #[test]
+    fn wildcard_accepts_unlisted_encodings() {
+        let raw = accepting("GZIP, *;q=0.1");
+        let request = Request::parse(&raw).unwrap();
+        assert!(request.accepts_encoding("gzip"));
+        assert!(request.accepts_encoding("br"));
+        let raw = accepting("gzip, *;q=0");
+        let request = Request::parse(&raw).unwrap();
+        assert!(request.accepts_encoding("gzip"));
+        assert!(!request.accepts_encoding("br"));
+        // A listed encoding takes precedence over the wildcard
+        let raw = accepting("*, br;q=0");
+        assert!(!Request::parse(&raw).unwrap().accepts_encoding("br"));
+        let request = Request::parse("GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(!request.accepts_encoding("gzip"));
+    }
+
+    /// Returns the range requested by the given `Range` header.
+    fn range(value: &str) -> Option<ByteRange> {
+        let raw = format!("GET / HTTP/1.1\r\nRange: {}\r\n\r\n", value);
+        Request::parse(&raw).unwrap().byte_range()
+    }
+
+    #[test]
+    fn byte_ranges_are_parsed() {
+        let parsed = |value| range(value).map(|range| (range.start, range.end));
+        assert_eq!(parsed("bytes=0-99"), Some((Some(0), Some(99))));
+        assert_eq!(parsed("bytes=100-"), Some((Some(100), None)));
+        assert_eq!(parsed("bytes=-50"), Some((None, Some(50))));
+        assert_eq!(parsed(" bytes= 5 - 9 "), Some((Some(5), Some(9))));
+        for value in &[
+            "bytes=-",
+            "bytes=9-5",
+            "bytes=0-1,3-4",
+            "items=0-1",
+            "bytes=a-b",
+        ] {
+            assert!(range(value).is_none(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn byte_ranges_are_resolved_within_the_resource() {
+        let resolved = |value, total| range(value).unwrap().resolve(total);
+        assert_eq!(resolved("bytes=2-5", 10), Some((2, 5)));
+        // The end is clamped to the last byte
+        assert_eq!(resolved("bytes=5-100", 10), Some((5, 9)));
+        assert_eq!(resolved("bytes=7-", 10), Some((7, 9)));
+        assert_eq!(resolved("bytes=-3", 10), Some((7, 9)));
+        // A suffix longer than the resource covers all of it
+        assert_eq!(resolved("bytes=-30", 10), Some((0, 9)));
+        assert_eq!(resolved("bytes=10-", 10), None);
+        assert_eq!(resolved("bytes=-0", 10), None);
+        assert_eq!(resolved("bytes=0-", 0), None);
     }
 }
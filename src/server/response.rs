@@ -1,26 +1,244 @@
 use crate::server::ContentHeaders;
-use chrono::Utc;
-use flate2::{write::GzEncoder, Compression};
-use std::collections::HashMap;
+use brotli::CompressorWriter;
+use chrono::{DateTime, Utc};
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
+use std::fs::File;
+use std::io;
 use std::io::Result;
 use std::io::Write;
-use std::net::TcpStream;
+use std::io::{BufReader, Take};
 
 pub enum HttpStatus {
+    Continue,
     Ok,
+    Created,
+    NoContent,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    NotModified,
     NotFound,
     BadRequest,
+    Unauthorized,
     NotAllowed,
+    PayloadTooLarge,
+    RangeNotSatisfiable,
+    ExpectationFailed,
+    MisdirectedRequest,
     ServerError,
+    ServiceUnavailable,
     UnsupportedVersion,
 }
 
+/// Format of the dates in HTTP headers, as defined in RFC 7231
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Size of the chunks a streamed file body is sent in
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Size of the brotli encoder's internal buffer
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// Base-2 logarithm of the brotli window size, the encoder's recommended default
+const BROTLI_WINDOW: u32 = 22;
+
+/// Default value of the `Server` header
+pub const SERVER_NAME: &str = concat!("http-server/", env!("CARGO_PKG_VERSION"));
+
+/// Caching instructions sent in the `Cache-Control` header
+#[derive(Clone, Copy)]
+pub enum CachePolicy {
+    /// Each file type is cached for its own age, see `HttpContent::content_headers`
+    PerType,
+    /// Every cacheable response is cached for the given number of seconds
+    MaxAge(u32),
+    /// Nothing is cached, so changes to the served files show up immediately
+    NoStore,
+}
+
+/// Settings from a server's configuration that apply to every response it sends
+///
+/// * `server_header` - Value of the `Server` header, or `None` to leave the header out
+/// * `headers` - Headers from the configuration, sent with every response
+/// * `cache_policy` - How long browsers may cache the served content, replacing the
+///   per-type ages
+#[derive(Clone)]
+pub struct ResponseSettings {
+    pub server_header: Option<String>,
+    pub headers: Vec<(&'static str, String)>,
+    pub cache_policy: CachePolicy,
+}
+
+impl Default for ResponseSettings {
+    fn default() -> Self {
+        ResponseSettings {
+            server_header: Some(String::from(SERVER_NAME)),
+            headers: Vec::new(),
+            cache_policy: CachePolicy::PerType,
+        }
+    }
+}
+
+/// Returns the given bytes compressed with a content encoding.
+/// Returns `io::Error` if the encoding isn't `br`, `gzip` or `deflate`, or compression fails.
+///
+/// # Arguments
+/// * `bytes` - The data to compress
+/// * `encoding` - The content encoding to compress with
+/// * `level` - Compression level, from 0 (none) to 9 (smallest but slowest).
+///   Brotli uses the level as its quality
+pub fn compress(bytes: &[u8], encoding: &str, level: u32) -> Result<Vec<u8>> {
+    let level = level.min(9);
+    match encoding {
+        "br" => {
+            let mut encoder =
+                CompressorWriter::new(Vec::new(), BROTLI_BUFFER_SIZE, level, BROTLI_WINDOW);
+            encoder.write_all(bytes)?;
+            // Taking the output finishes the stream
+            Ok(encoder.into_inner())
+        }
+        "gzip" => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+            encoder.write_all(bytes)?;
+            encoder.finish()
+        }
+        "deflate" => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(level));
+            encoder.write_all(bytes)?;
+            encoder.finish()
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported content encoding {}", encoding),
+        )),
+    }
+}
+
 /// Struct representing a HTTP Response
+///
+/// * `headers` - The response's headers, sent in insertion order
+/// * `suppress_body` - Whether to send only the headers, as in a response to a HEAD request.
+///   The headers are still those of the full, possibly compressed payload, so the
+///   `Content-Length` of a HEAD response is the length a GET would receive
+/// * `file_body` - A file streamed as the body instead of `payload`
+/// * `chunked` - Whether the body is sent with the chunked transfer coding, see `set_chunked`
 pub struct Response<'a> {
     pub status: HttpStatus,
     pub protocol: String,
-    pub headers: HashMap<&'a str, String>,
+    pub headers: Vec<(&'a str, String)>,
     pub payload: Vec<u8>,
+    pub suppress_body: bool,
+    pub file_body: Option<Take<File>>,
+    pub chunked: bool,
+}
+
+/// Writes everything written to it as chunks of the chunked transfer coding,
+/// each prefixed with its size. The terminating empty chunk is written by `finish`.
+struct ChunkedWriter<'w, W: Write + ?Sized> {
+    inner: &'w mut W,
+}
+
+impl<W: Write + ?Sized> ChunkedWriter<'_, W> {
+    /// Ends the body with the empty last chunk.
+    fn finish(self) -> Result<()> {
+        self.inner.write_all(b"0\r\n\r\n")
+    }
+}
+
+impl<W: Write + ?Sized> Write for ChunkedWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        // An empty chunk would end the body early
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.inner, "{:X}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl HttpStatus {
+    /// Returns the numeric status code.
+    pub fn code(&self) -> u16 {
+        match self {
+            HttpStatus::Continue => 100,
+            HttpStatus::Ok => 200,
+            HttpStatus::Created => 201,
+            HttpStatus::NoContent => 204,
+            HttpStatus::PartialContent => 206,
+            HttpStatus::MovedPermanently => 301,
+            HttpStatus::Found => 302,
+            HttpStatus::NotModified => 304,
+            HttpStatus::NotFound => 404,
+            HttpStatus::BadRequest => 400,
+            HttpStatus::Unauthorized => 401,
+            HttpStatus::NotAllowed => 405,
+            HttpStatus::PayloadTooLarge => 413,
+            HttpStatus::RangeNotSatisfiable => 416,
+            HttpStatus::ExpectationFailed => 417,
+            HttpStatus::MisdirectedRequest => 421,
+            HttpStatus::ServerError => 500,
+            HttpStatus::ServiceUnavailable => 503,
+            HttpStatus::UnsupportedVersion => 505,
+        }
+    }
+
+    /// Returns the standard reason phrase for the status, e.g. `Not Found`.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            HttpStatus::Continue => "Continue",
+            HttpStatus::Ok => "OK",
+            HttpStatus::Created => "Created",
+            HttpStatus::NoContent => "No Content",
+            HttpStatus::PartialContent => "Partial Content",
+            HttpStatus::MovedPermanently => "Moved Permanently",
+            HttpStatus::Found => "Found",
+            HttpStatus::NotModified => "Not Modified",
+            HttpStatus::NotFound => "Not Found",
+            HttpStatus::BadRequest => "Bad Request",
+            HttpStatus::Unauthorized => "Unauthorized",
+            HttpStatus::NotAllowed => "Method Not Allowed",
+            HttpStatus::PayloadTooLarge => "Payload Too Large",
+            HttpStatus::RangeNotSatisfiable => "Range Not Satisfiable",
+            HttpStatus::ExpectationFailed => "Expectation Failed",
+            HttpStatus::MisdirectedRequest => "Misdirected Request",
+            HttpStatus::ServerError => "Internal Server Error",
+            HttpStatus::ServiceUnavailable => "Service Unavailable",
+            HttpStatus::UnsupportedVersion => "HTTP Version Not Supported",
+        }
+    }
+}
+
+/// Returns a `Content-Disposition` value telling browsers to save the content as a file
+/// with the given name. The plain `filename` parameter is an ASCII fallback, and names with
+/// other characters are also sent percent-encoded as UTF-8, as described in RFC 6266.
+fn attachment_disposition(name: &str) -> String {
+    let mut fallback = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '"' | '\\' => {
+                fallback.push('\\');
+                fallback.push(c);
+            }
+            ' '..='~' => fallback.push(c),
+            _ => fallback.push('_'),
+        }
+    }
+    if name.chars().all(|c| (' '..='~').contains(&c)) {
+        format!("attachment; filename=\"{}\"", fallback)
+    } else {
+        format!(
+            "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+            fallback,
+            urlencoding::encode(name)
+        )
+    }
 }
 
 impl<'a> Response<'a> {
@@ -33,20 +251,20 @@ impl<'a> Response<'a> {
         Response {
             status,
             protocol: String::from("HTTP/1.1"),
-            headers: HashMap::new(),
+            headers: Vec::new(),
             payload,
+            suppress_body: false,
+            file_body: None,
+            chunked: false,
         }
     }
 
-    fn status_to_string(&self) -> &str {
-        match &self.status {
-            HttpStatus::Ok => "200 OK",
-            HttpStatus::NotFound => "404 NOT FOUND",
-            HttpStatus::BadRequest => "400 BAD REQUEST",
-            HttpStatus::NotAllowed => "405 METHOD NOT ALLOWED",
-            HttpStatus::ServerError => "500 INTERNAL SERVER ERROR",
-            HttpStatus::UnsupportedVersion => "505 HTTP VERSION NOT SUPPORTED",
-        }
+    fn status_to_string(&self) -> String {
+        format!(
+            "{} {}",
+            self.status.code(),
+            self.status.reason().to_uppercase()
+        )
     }
 
     fn headers_to_string(&self) -> String {
@@ -56,56 +274,336 @@ impl<'a> Response<'a> {
             &self.protocol,
             &self.status_to_string()
         ));
-        for kv in &self.headers {
-            let line = format!("{}: {}\r\n", kv.0, kv.1);
+        for (name, value) in &self.headers {
+            let line = format!("{}: {}\r\n", name, value);
             result.push_str(&line);
         }
         result.push_str("\r\n");
         result
     }
 
-    /// Add common headers to the response.
-    pub fn set_default_headers(&mut self) {
-        let length = self.payload.len().to_string();
-        let time = Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string();
-
-        self.headers.insert("Connection", String::from("close"));
-        self.headers.insert("Content-Length", length);
-        self.headers.insert("Date", time);
+    /// Returns the value of the given header, if it has been set.
+    /// Header names are case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
         self.headers
-            .insert("Permissions-Policy", "interest-cohort=()".to_string());
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
     }
 
-    /// Add content type specific headers to the response.
-    pub fn set_content_headers(&mut self, headers: &ContentHeaders) {
-        let cache_control = format!("max-age={}", headers.cache_age);
+    /// Set a header, replacing the value of an existing header with the same name.
+    /// New headers are sent after the ones already set.
+    ///
+    /// # Arguments
+    /// * `name` - The header's name
+    /// * `value` - The header's value
+    pub fn set_header(&mut self, name: &'a str, value: String) {
+        match self
+            .headers
+            .iter_mut()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        {
+            Some(header) => header.1 = value,
+            None => self.headers.push((name, value)),
+        }
+    }
 
+    /// Remove a header from the response, if it has been set.
+    pub fn remove_header(&mut self, name: &str) {
         self.headers
-            .insert("Content-Type", headers.content_type.to_string());
-        self.headers.insert("Cache-Control", cache_control);
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    /// Add common headers to the response, including those from the server's settings.
+    pub fn set_default_headers(&mut self, settings: &ResponseSettings) {
+        let length = self.payload.len().to_string();
+        let time = Utc::now().format(HTTP_DATE_FORMAT).to_string();
+
+        self.set_header("Date", time);
+        self.set_header("Connection", String::from("close"));
+        self.set_header("Content-Length", length);
+        self.set_header("Permissions-Policy", "interest-cohort=()".to_string());
+        if let Some(server) = &settings.server_header {
+            self.set_header("Server", server.clone());
+        }
+        for (name, value) in &settings.headers {
+            self.set_header(name, value.clone());
+        }
+    }
+
+    /// Keep the connection open after the response, instead of closing it.
+    ///
+    /// # Arguments
+    /// * `timeout` - How long the connection is kept open while idle, in seconds
+    /// * `max` - How many more requests the connection may send, or `None` if unlimited
+    pub fn set_keep_alive(&mut self, timeout: u64, max: Option<usize>) {
+        let value = match max {
+            Some(max) => format!("timeout={}, max={}", timeout, max),
+            None => format!("timeout={}", timeout),
+        };
+        self.set_header("Connection", String::from("keep-alive"));
+        self.set_header("Keep-Alive", value);
     }
 
-    /// Compress the response payload using gzip, and set the correct encoding headers.
-    pub fn compress_gzip(&mut self) -> Result<()> {
-        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(&self.payload)?;
-        self.payload = encoder.finish()?;
+    /// Add content type specific headers to the response, including `Vary: Accept-Encoding`
+    /// for compressible content.
+    /// The age is replaced according to the given `CachePolicy`, except for
+    /// content that must not be cached at all.
+    pub fn set_content_headers(&mut self, headers: &ContentHeaders, policy: CachePolicy) {
+        let cache_control = match policy {
+            CachePolicy::NoStore => String::from("no-store"),
+            CachePolicy::MaxAge(age) if headers.cache_age > 0 => format!("max-age={}", age),
+            _ => format!("max-age={}", headers.cache_age),
+        };
+
+        let content_type = match &headers.charset {
+            Some(charset) => format!("{}; charset={}", headers.content_type, charset),
+            None => headers.content_type.to_string(),
+        };
+        self.set_header("Content-Type", content_type);
+        self.set_header("Cache-Control", cache_control);
+        if let Some(etag) = &headers.etag {
+            self.set_header("ETag", etag.clone());
+        }
+        if let Some(modified) = headers.last_modified {
+            let modified = DateTime::<Utc>::from(modified).format(HTTP_DATE_FORMAT);
+            self.set_header("Last-Modified", modified.to_string());
+        }
+        if headers.accept_ranges {
+            self.set_header("Accept-Ranges", String::from("bytes"));
+        }
+        // Whether the body is compressed depends on the request, even when it isn't this time,
+        // so shared caches must not hand this response to clients with other encodings
+        if headers.compress {
+            self.set_header("Vary", String::from("Accept-Encoding"));
+        }
+        if let Some(name) = &headers.download_name {
+            self.set_header("Content-Disposition", attachment_disposition(name));
+        }
+    }
 
-        self.headers.insert("Content-Encoding", "gzip".to_string());
-        self.headers.insert("Vary", "Accept-Encoding".to_string());
+    /// Stream the given file as the response body instead of the payload.
+    ///
+    /// # Arguments
+    /// * `file` - The file to send, limited to the bytes that should be sent
+    pub fn set_file_body(&mut self, file: Take<File>) {
+        self.set_header("Content-Length", file.limit().to_string());
+        self.file_body = Some(file);
+    }
+
+    /// Send the body with the chunked transfer coding, so its length doesn't need to be
+    /// known up front. Only HTTP/1.1 clients understand the coding.
+    pub fn set_chunked(&mut self) {
+        self.remove_header("Content-Length");
+        self.set_header("Transfer-Encoding", String::from("chunked"));
+        self.chunked = true;
+    }
+
+    /// Set the `Content-Range` header for a partial response.
+    ///
+    /// # Arguments
+    /// * `range` - The inclusive start and end offsets of the payload,
+    ///   or `None` if the requested range could not be satisfied
+    /// * `total` - The size of the complete resource in bytes
+    pub fn set_content_range(&mut self, range: Option<(u64, u64)>, total: u64) {
+        let value = match range {
+            Some((start, end)) => format!("bytes {}-{}/{}", start, end, total),
+            None => format!("bytes */{}", total),
+        };
+        self.set_header("Content-Range", value);
+    }
+
+    /// Set the encoding headers for a compressed body.
+    ///
+    /// # Arguments
+    /// * `encoding` - The body's content encoding, e.g. `gzip`
+    pub fn set_content_encoding(&mut self, encoding: &str) {
+        self.set_header("Content-Encoding", encoding.to_string());
+        // The compressed bytes differ from the file, so the tag can only be a weak match
+        if let Some(etag) = self.header("ETag") {
+            if !etag.starts_with("W/") {
+                let weak = format!("W/{}", etag);
+                self.set_header("ETag", weak);
+            }
+        }
+        self.set_header("Vary", "Accept-Encoding".to_string());
+    }
+
+    /// Compress the response payload using brotli at the given level, and set the correct
+    /// encoding headers.
+    pub fn compress_brotli(&mut self, level: u32) -> Result<()> {
+        self.payload = compress(&self.payload, "br", level)?;
+
+        self.set_content_encoding("br");
+        self.set_header("Content-Length", self.payload.len().to_string());
+        Ok(())
+    }
+
+    /// Compress the response payload using gzip at the given level, and set the correct
+    /// encoding headers.
+    pub fn compress_gzip(&mut self, level: u32) -> Result<()> {
+        self.payload = compress(&self.payload, "gzip", level)?;
+
+        self.set_content_encoding("gzip");
         // Update content length
-        self.headers
-            .insert("Content-Length", self.payload.len().to_string());
+        self.set_header("Content-Length", self.payload.len().to_string());
         Ok(())
     }
 
-    /// Write the response to the given `TcpStream`.
-    pub fn send(&self, stream: &mut TcpStream, include_body: bool) -> Result<()> {
-        stream.write_all(&self.headers_to_string().as_bytes())?;
-        if include_body {
-            stream.write_all(&self.payload)?;
+    /// Compress the response payload using deflate at the given level, and set the correct
+    /// encoding headers.
+    pub fn compress_deflate(&mut self, level: u32) -> Result<()> {
+        self.payload = compress(&self.payload, "deflate", level)?;
+
+        self.set_content_encoding("deflate");
+        self.set_header("Content-Length", self.payload.len().to_string());
+        Ok(())
+    }
+
+    /// Write the response to the given writer, such as a `TcpStream` or a TLS stream.
+    /// The payload is omitted if `suppress_body` is set.
+    /// Returns the number of body bytes written, not counting the chunked coding.
+    pub fn send<W: Write + ?Sized>(&mut self, stream: &mut W) -> Result<u64> {
+        stream.write_all(self.headers_to_string().as_bytes())?;
+        let mut written = 0;
+        if !self.suppress_body {
+            if self.chunked {
+                let mut writer = ChunkedWriter { inner: stream };
+                written = self.write_body(&mut writer)?;
+                writer.finish()?;
+            } else {
+                written = self.write_body(stream)?;
+            }
         }
         stream.flush()?;
-        Ok(())
+        Ok(written)
+    }
+
+    /// Writes the file body, or the payload if there is no file.
+    /// Returns the number of bytes written.
+    fn write_body<W: Write + ?Sized>(&mut self, writer: &mut W) -> Result<u64> {
+        match self.file_body.take() {
+            Some(file) => {
+                let mut reader = BufReader::with_capacity(STREAM_CHUNK_SIZE, file);
+                io::copy(&mut reader, writer)
+            }
+            None => {
+                for chunk in self.payload.chunks(STREAM_CHUNK_SIZE) {
+                    writer.write_all(chunk)?;
+                }
+                Ok(self.payload.len() as u64)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::{DeflateDecoder, GzDecoder};
+    use std::io::Read;
+
+    const TEXT: &[u8] = b"Lorem ipsum dolor sit amet, lorem ipsum dolor sit amet. ";
+
+    fn decompress(bytes: &[u8], encoding: &str) -> Vec<u8> {
+        let mut output = Vec::new();
+        match encoding {
+            "br" => brotli::Decompressor::new(bytes, BROTLI_BUFFER_SIZE).read_to_end(&mut output),
+            "gzip" => GzDecoder::new(bytes).read_to_end(&mut output),
+            _ => DeflateDecoder::new(bytes).read_to_end(&mut output),
+        }
+        .unwrap();
+        output
+    }
+
+    #[test]
+    fn compressed_bytes_round_trip() {
+        let text = TEXT.repeat(20);
+        for encoding in ["br", "gzip", "deflate"] {
+            let compressed = compress(&text, encoding, 6).unwrap();
+            assert!(
+                compressed.len() < text.len(),
+                "{} didn't compress",
+                encoding
+            );
+            assert_eq!(decompress(&compressed, encoding), text);
+        }
+    }
+
+    #[test]
+    fn higher_levels_compress_no_worse() {
+        // Words in a repeatable but irregular order, which faster levels match less of
+        let words = ["lorem", "ipsum", "dolor", "sit", "amet", "consectetur"];
+        let mut seed = 1u32;
+        let mut text = Vec::new();
+        for _ in 0..5000 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            text.extend_from_slice(words[(seed >> 16) as usize % words.len()].as_bytes());
+            text.push(b' ');
+        }
+        for encoding in ["br", "gzip", "deflate"] {
+            let fastest = compress(&text, encoding, 1).unwrap();
+            let smallest = compress(&text, encoding, 9).unwrap();
+            assert!(
+                smallest.len() <= fastest.len(),
+                "{}: level 9 gave {} bytes, level 1 gave {}",
+                encoding,
+                smallest.len(),
+                fastest.len()
+            );
+            assert_eq!(decompress(&smallest, encoding), text);
+        }
+    }
+
+    #[test]
+    fn unknown_encoding_is_an_error() {
+        assert!(compress(TEXT, "zstd", 6).is_err());
+    }
+
+    #[test]
+    fn compress_brotli_sets_encoding_headers() {
+        let mut response = Response::new(HttpStatus::Ok, TEXT.repeat(20));
+        response.set_header("ETag", String::from("\"abc\""));
+        response.compress_brotli(6).unwrap();
+        assert_eq!(response.header("Content-Encoding"), Some("br"));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.header("ETag"), Some("W/\"abc\""));
+        let length = response.payload.len().to_string();
+        assert_eq!(response.header("Content-Length"), Some(length.as_str()));
+        assert_eq!(decompress(&response.payload, "br"), TEXT.repeat(20));
+    }
+
+    #[test]
+    fn headers_are_sent_in_the_order_they_were_first_set() {
+        let mut response = Response::new(HttpStatus::Ok, b"body".to_vec());
+        response.set_header("Content-Type", String::from("text/plain"));
+        response.set_header("X-First", String::from("1"));
+        response.set_header("Content-Length", String::from("4"));
+        // Replacing a value keeps the header in place
+        response.set_header("x-first", String::from("2"));
+        response.remove_header("X-Missing");
+        let mut sent = Vec::new();
+        assert_eq!(response.send(&mut sent).unwrap(), 4);
+        assert_eq!(
+            String::from_utf8(sent).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nX-First: 2\r\n\
+             Content-Length: 4\r\n\r\nbody"
+        );
+    }
+
+    #[test]
+    fn attachment_names_are_quoted() {
+        assert_eq!(
+            attachment_disposition("report 2024.pdf"),
+            "attachment; filename=\"report 2024.pdf\""
+        );
+        assert_eq!(
+            attachment_disposition("say \"hi\"\\.zip"),
+            "attachment; filename=\"say \\\"hi\\\"\\\\.zip\""
+        );
+        assert_eq!(
+            attachment_disposition("café.txt"),
+            "attachment; filename=\"caf_.txt\"; filename*=UTF-8''caf%C3%A9.txt"
+        );
     }
 }
@@ -1,36 +1,83 @@
-use std::sync::{mpsc, Arc, Mutex};
+use crossbeam_channel::{Receiver, Sender};
+use std::any::Any;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
 use crate::{log, LogLevel};
 
 /// Threadpool for handling multiple HTTP requests simultaneously
+///
+/// * `queued` - The number of jobs waiting for a worker
+/// * `queue_size` - The maximum number of jobs waiting for a worker
 pub struct ThreadPool {
     workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
+    sender: Sender<Message>,
+    queued: Arc<AtomicUsize>,
+    queue_size: usize,
 }
 
 impl ThreadPool {
 
-    /// Returns a new `Threadpool` instance.
+    /// Returns a new `Threadpool` instance, with workers named `http-worker-<id>`.
+    /// Returns `io::Error` if there are no threads or no room in the queue, or if a worker
+    /// thread could not be started.
     ///
     /// # Arguments
     /// * `max_threads` - the amount of worker threads in the pool
-    pub fn new(max_threads: usize) -> ThreadPool {
-        assert!(max_threads > 0);
-
-        let (sender, receiver) = mpsc::channel();
+    /// * `queue_size` - the maximum amount of jobs waiting for a worker
+    /// * `stack_size` - the stack size of each worker in bytes, or `None` for the default
+    pub fn new(
+        max_threads: usize,
+        queue_size: usize,
+        stack_size: Option<usize>,
+    ) -> io::Result<ThreadPool> {
+        if max_threads == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "at least one thread is required",
+            ));
+        }
+        if queue_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the queue size must be at least one",
+            ));
+        }
 
-        let receiver = Arc::new(Mutex::new(receiver));
+        // Every worker has its own handle to the queue, so they don't contend on a lock
+        let (sender, receiver) = crossbeam_channel::bounded(queue_size);
+        let queued = Arc::new(AtomicUsize::new(0));
 
         let mut workers = Vec::<Worker>::with_capacity(max_threads);
 
+        // Workers started before a failure stop once the sender is dropped
         for id in 0..max_threads {
-            workers.push(Worker::new(id, Arc::clone(&receiver)))
+            workers.push(Worker::new(
+                id,
+                receiver.clone(),
+                Arc::clone(&queued),
+                stack_size,
+            )?)
         }
 
-        ThreadPool { workers, sender }
+        Ok(ThreadPool {
+            workers,
+            sender,
+            queued,
+            queue_size,
+        })
     }
 
-    /// Queue a new job to execute.
+    /// Returns whether the queue is full, in which case `execute` blocks until
+    /// a worker takes the next job.
+    pub fn is_full(&self) -> bool {
+        self.queued.load(Ordering::SeqCst) >= self.queue_size
+    }
+
+    /// Queue a new job to execute, waiting for room in the queue if it is full.
+    /// The job is dropped with an error log if no workers are left to receive it.
     ///
     /// # Arguments
     /// * `f` - The job to execute when a thread is available
@@ -39,7 +86,14 @@ impl ThreadPool {
         F: FnOnce() + Send + 'static,
     {
         let job = Box::new(f);
-        self.sender.send(Message::NewJob(job)).unwrap();
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        if self.sender.send(Message::NewJob(job)).is_err() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            log(
+                "Could not queue a job, all workers have stopped.",
+                LogLevel::ServerError,
+            );
+        }
     }
 }
 
@@ -47,13 +101,19 @@ impl Drop for ThreadPool {
     fn drop(&mut self) {
         log("Shutting down all workers.", LogLevel::Info);
 
+        // Sending fails only if every worker has already stopped, which is fine here
         for _ in &self.workers {
-            self.sender.send(Message::Terminate).unwrap();
+            let _ = self.sender.send(Message::Terminate);
         }
 
-        for worker in &mut self.workers {
+        for (id, worker) in self.workers.iter_mut().enumerate() {
             if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+                if thread.join().is_err() {
+                    log(
+                        &format!("Worker #{} stopped unexpectedly.", id),
+                        LogLevel::ServerError,
+                    );
+                }
             }
         }
     }
@@ -64,23 +124,53 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
-            match message {
-                Message::NewJob(job) => {
+    fn new(
+        id: usize,
+        receiver: Receiver<Message>,
+        queued: Arc<AtomicUsize>,
+        stack_size: Option<usize>,
+    ) -> io::Result<Worker> {
+        let mut builder = thread::Builder::new().name(format!("http-worker-{}", id));
+        if let Some(size) = stack_size {
+            builder = builder.stack_size(size);
+        }
+        let thread = builder.spawn(move || loop {
+            match receiver.recv() {
+                Ok(Message::NewJob(job)) => {
+                    queued.fetch_sub(1, Ordering::SeqCst);
                     log(&format!("Worker #{} got a job; executing.", id), LogLevel::Info);
-                    job();
+                    // Keep the worker alive if the job panics, so the pool doesn't shrink
+                    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                        log(
+                            &format!(
+                                "Worker #{} recovered from a panic: {}",
+                                id,
+                                panic_message(&*payload)
+                            ),
+                            LogLevel::ServerError,
+                        );
+                    }
                 }
-                Message::Terminate => {
+                Ok(Message::Terminate) | Err(_) => {
                     log(&format!("Shutting down worker #{}", id), LogLevel::Info);
                     break;
                 }
             }
-        });
-        Worker {
+        })?;
+        Ok(Worker {
             thread: Some(thread),
-        }
+        })
+    }
+}
+
+/// Returns the message of a panic payload, if it has one.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown cause"
     }
 }
 
@@ -90,3 +180,76 @@ enum Message {
     NewJob(Job),
     Terminate,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn every_job_runs_exactly_once() {
+        const JOBS: usize = 1000;
+        let runs: Arc<Vec<AtomicUsize>> =
+            Arc::new((0..JOBS).map(|_| AtomicUsize::new(0)).collect());
+        {
+            let pool = ThreadPool::new(4, 16, None).unwrap();
+            for job in 0..JOBS {
+                let runs = Arc::clone(&runs);
+                pool.execute(move || {
+                    runs[job].fetch_add(1, Ordering::SeqCst);
+                });
+            }
+            // Dropping the pool waits for the queued jobs before the workers terminate
+        }
+        assert!(runs.iter().all(|count| count.load(Ordering::SeqCst) == 1));
+    }
+
+    #[test]
+    fn jobs_run_on_several_workers() {
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        {
+            let pool = ThreadPool::new(2, 4, None).unwrap();
+            for _ in 0..2 {
+                let (names, barrier) = (Arc::clone(&names), Arc::clone(&barrier));
+                pool.execute(move || {
+                    // Neither job finishes until both have started, so they can't share a worker
+                    barrier.wait();
+                    let name = thread::current().name().map(String::from);
+                    names.lock().unwrap().push(name);
+                });
+            }
+        }
+        let mut names = names.lock().unwrap().clone();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                Some(String::from("http-worker-0")),
+                Some(String::from("http-worker-1"))
+            ]
+        );
+    }
+
+    #[test]
+    fn panicking_job_keeps_the_worker() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        {
+            let pool = ThreadPool::new(1, 4, None).unwrap();
+            pool.execute(|| panic!("job failed"));
+            let ran = Arc::clone(&ran);
+            pool.execute(move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn empty_pool_or_queue_is_rejected() {
+        for (threads, queue_size) in [(0, 4), (4, 0)] {
+            let err = ThreadPool::new(threads, queue_size, None).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+}
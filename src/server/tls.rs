@@ -0,0 +1,70 @@
+use rustls::crypto::ring::default_provider;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+/// A client connection, either plain or encrypted with TLS
+pub trait Connection: Read + Write {
+    /// Returns the underlying socket, for its address and timeouts.
+    fn socket(&self) -> &TcpStream;
+
+    /// Ends the connection once the server is done with it.
+    fn close(&mut self) {}
+}
+
+impl Connection for TcpStream {
+    fn socket(&self) -> &TcpStream {
+        self
+    }
+}
+
+/// A client connection encrypted with TLS
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+impl Connection for TlsStream {
+    fn socket(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    /// Tells the client that no more data follows, so it can tell the end of the
+    /// connection from a truncation attack.
+    fn close(&mut self) {
+        self.conn.send_close_notify();
+        // Flushing would wait for the client to continue an unfinished handshake
+        while self.conn.wants_write() {
+            if self.conn.write_tls(&mut self.sock).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Returns the TLS configuration for serving with the certificate chain and private key
+/// in the given PEM files.
+/// Returns `io::Error` if the files could not be read or don't hold a usable certificate
+/// and key.
+///
+/// # Arguments
+/// * `cert_path` - Path of the certificate chain, starting with the server's certificate
+/// * `key_path` - Path of the certificate's private key
+pub fn tls_config(cert_path: &str, key_path: &str) -> Result<Arc<ServerConfig>> {
+    let invalid = |reason: String| Error::new(ErrorKind::InvalidData, reason);
+
+    let mut reader = BufReader::new(File::open(cert_path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(invalid(format!("no certificates in {}", cert_path)));
+    }
+    let mut reader = BufReader::new(File::open(key_path)?);
+    let key = rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| invalid(format!("no private key in {}", key_path)))?;
+
+    let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|err| invalid(err.to_string()))?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
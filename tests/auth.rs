@@ -0,0 +1,44 @@
+mod common;
+
+use common::{TempDir, TestServer};
+use http_server::ServerBuilder;
+
+/// Serves a page that requires the credentials `user:secret`.
+fn protected_server() -> (TempDir, TestServer) {
+    let dir = TempDir::new();
+    dir.write("index.html", "<p>Private</p>");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()).auth("user:secret"));
+    (dir, server)
+}
+
+#[test]
+fn clients_without_credentials_are_asked_to_log_in() {
+    let (_dir, server) = protected_server();
+    let response = server.get("/");
+    assert_eq!(response.status, 401);
+    assert!(response
+        .header("WWW-Authenticate")
+        .unwrap()
+        .starts_with("Basic realm="));
+    assert!(!response.text().contains("Private"));
+}
+
+#[test]
+fn clients_with_the_credentials_are_served() {
+    let (_dir, server) = protected_server();
+    // base64 of "user:secret"
+    let response = server.request("GET", "/", &[("Authorization", "Basic dXNlcjpzZWNyZXQ=")]);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "<p>Private</p>");
+}
+
+#[test]
+fn clients_with_wrong_credentials_are_refused() {
+    let (_dir, server) = protected_server();
+    // base64 of "user:guess"
+    for header in &["Basic dXNlcjpndWVzcw==", "Bearer dXNlcjpzZWNyZXQ="] {
+        let response = server.request("GET", "/", &[("Authorization", header)]);
+        assert_eq!(response.status, 401, "{}", header);
+        assert!(!response.text().contains("Private"));
+    }
+}
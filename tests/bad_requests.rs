@@ -0,0 +1,16 @@
+mod common;
+
+use common::{parse_responses, TempDir, TestServer};
+use http_server::ServerBuilder;
+
+#[test]
+fn malformed_header_lines_get_bad_request() {
+    let dir = TempDir::new();
+    dir.write("page.txt", "page");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    for header in &["X-Missing-Colon", "Host : localhost"] {
+        let request = format!("GET /page.txt HTTP/1.1\r\n{}\r\n\r\n", header);
+        let response = parse_responses(&server.exchange(request), false).remove(0);
+        assert_eq!(response.status, 400, "{}", header);
+    }
+}
@@ -0,0 +1,232 @@
+//! Helpers for running a server on a loopback port and talking to it over raw sockets.
+#![allow(dead_code)]
+
+use http_server::{ServerBuilder, ShutdownHandle};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A directory under the system's temporary directory, removed when dropped
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates an empty directory with a name unique to this process and call.
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "http-server-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The directory's path as a string, for the builder
+    pub fn as_str(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+
+    /// Writes a file relative to the directory, creating its parent directories.
+    pub fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.path.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A server running on its own thread, stopped when dropped
+pub struct TestServer {
+    pub address: SocketAddr,
+    handle: ShutdownHandle,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl TestServer {
+    /// Starts the configured server on a free loopback port and waits until it accepts
+    /// connections.
+    pub fn start(builder: ServerBuilder) -> Self {
+        let handle = ShutdownHandle::new();
+        let config = builder
+            .port(0)
+            .log_level(None)
+            .shutdown_handle(&handle)
+            .build();
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            http_server::run_with(config, |address| sender.send(address).unwrap())
+        });
+        let address = receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("server did not start");
+        TestServer {
+            address,
+            handle,
+            thread: Some(thread),
+        }
+    }
+
+    /// Opens a new connection to the server.
+    pub fn connect(&self) -> TcpStream {
+        let stream = TcpStream::connect(self.address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        stream
+    }
+
+    /// Sends the raw request on a new connection and returns everything the server wrote
+    /// before closing it.
+    pub fn exchange(&self, request: impl AsRef<[u8]>) -> Vec<u8> {
+        let mut stream = self.connect();
+        stream.write_all(request.as_ref()).unwrap();
+        read_to_close(&mut stream)
+    }
+
+    /// Sends a request closing the connection and parses the response.
+    pub fn request(&self, method: &str, path: &str, headers: &[(&str, &str)]) -> Response {
+        let mut raw = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n", method, path);
+        for (name, value) in headers {
+            raw.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        raw.push_str("Connection: close\r\n\r\n");
+        let bytes = self.exchange(raw);
+        parse_responses(&bytes, method == "HEAD").remove(0)
+    }
+
+    pub fn get(&self, path: &str) -> Response {
+        self.request("GET", path, &[])
+    }
+
+    /// Stops the server and waits for it to return.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.handle.stop();
+        self.thread.take().unwrap().join().unwrap()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Reads from the stream until the server closes it or the read times out.
+pub fn read_to_close(stream: &mut TcpStream) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut buffer = [0; 8192];
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => return bytes,
+            Ok(n) => bytes.extend_from_slice(&buffer[..n]),
+        }
+    }
+}
+
+/// A parsed HTTP response
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Returns the value of the first header with the name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Splits the bytes into consecutive responses, using their Content-Length or chunked
+/// transfer coding to find the end of each body. Responses to HEAD requests have no body
+/// regardless of their headers.
+pub fn parse_responses(mut bytes: &[u8], head: bool) -> Vec<Response> {
+    let mut responses = Vec::new();
+    while !bytes.is_empty() {
+        let end = bytes
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("incomplete response head");
+        let head_text = std::str::from_utf8(&bytes[..end]).unwrap();
+        let mut lines = head_text.split("\r\n");
+        let status = lines.next().unwrap().split(' ').nth(1).unwrap();
+        let headers: Vec<(String, String)> = lines
+            .map(|line| {
+                let (name, value) = line.split_once(':').unwrap();
+                (name.to_string(), value.trim().to_string())
+            })
+            .collect();
+        let status: u16 = status.parse().unwrap();
+        let response = Response {
+            status,
+            headers,
+            body: Vec::new(),
+        };
+        bytes = &bytes[end + 4..];
+        if head || status == 100 || status == 304 {
+            responses.push(response);
+            continue;
+        }
+        let (body, rest) = if response.header("Transfer-Encoding") == Some("chunked") {
+            decode_chunked(bytes)
+        } else {
+            let length = response
+                .header("Content-Length")
+                .map_or(0, |value| value.parse().unwrap());
+            (bytes[..length].to_vec(), &bytes[length..])
+        };
+        responses.push(Response { body, ..response });
+        bytes = rest;
+    }
+    responses
+}
+
+/// Decodes a body sent with the chunked transfer coding, returning it along with the bytes
+/// that follow it.
+fn decode_chunked(mut bytes: &[u8]) -> (Vec<u8>, &[u8]) {
+    let mut body = Vec::new();
+    loop {
+        let line_end = bytes
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .expect("incomplete chunk size");
+        let size = std::str::from_utf8(&bytes[..line_end]).unwrap();
+        let size = usize::from_str_radix(size.split(';').next().unwrap().trim(), 16).unwrap();
+        let start = line_end + 2;
+        if size == 0 {
+            // No trailers are sent, so the last chunk is followed by an empty line
+            return (body, &bytes[start + 2..]);
+        }
+        body.extend_from_slice(&bytes[start..start + size]);
+        bytes = &bytes[start + size + 2..];
+    }
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::{TempDir, TestServer};
+use http_server::ServerBuilder;
+
+/// A text file large enough to be compressed
+fn served_text() -> (TempDir, String) {
+    let dir = TempDir::new();
+    let text = "body { color: black; }\n".repeat(200);
+    dir.write("style.css", &text);
+    (dir, text)
+}
+
+#[test]
+fn brotli_is_preferred_over_gzip() {
+    let (dir, _) = served_text();
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    let response = server.request("GET", "/style.css", &[("Accept-Encoding", "br, gzip")]);
+    assert_eq!(response.header("Content-Encoding"), Some("br"));
+    assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    // The order the client lists the encodings in doesn't matter
+    let response = server.request("GET", "/style.css", &[("Accept-Encoding", "gzip, br")]);
+    assert_eq!(response.header("Content-Encoding"), Some("br"));
+}
+
+#[test]
+fn gzip_is_used_without_brotli() {
+    let (dir, _) = served_text();
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    let response = server.request("GET", "/style.css", &[("Accept-Encoding", "gzip")]);
+    assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    let response = server.request("GET", "/style.css", &[("Accept-Encoding", "br;q=0, gzip")]);
+    assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+}
+
+#[test]
+fn uncompressed_without_a_supported_encoding() {
+    let (dir, text) = served_text();
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    let response = server.request("GET", "/style.css", &[("Accept-Encoding", "zstd")]);
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    assert_eq!(response.text(), text);
+}
+
+#[test]
+fn cached_files_are_compressed_per_encoding() {
+    let (dir, _) = served_text();
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()).cache_size(1 << 20));
+    for encoding in ["br", "gzip", "br"] {
+        let response = server.request("GET", "/style.css", &[("Accept-Encoding", encoding)]);
+        assert_eq!(response.header("Content-Encoding"), Some(encoding));
+    }
+}
+
+#[test]
+fn both_variants_vary_on_accept_encoding() {
+    let (dir, text) = served_text();
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    let plain = server.get("/style.css");
+    assert_eq!(plain.header("Content-Encoding"), None);
+    assert_eq!(plain.text(), text);
+    let compressed = server.request("GET", "/style.css", &[("Accept-Encoding", "gzip")]);
+    assert_eq!(compressed.header("Content-Encoding"), Some("gzip"));
+    for response in &[plain, compressed] {
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    }
+}
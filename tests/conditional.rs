@@ -0,0 +1,59 @@
+mod common;
+
+use common::{parse_responses, TempDir, TestServer};
+use http_server::ServerBuilder;
+use std::fs::File;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[test]
+fn matching_etag_gets_not_modified() {
+    let dir = TempDir::new();
+    dir.write("page.txt", "cached");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+
+    let response = server.get("/page.txt");
+    assert_eq!(response.status, 200);
+    let etag = response.header("ETag").expect("no ETag").to_string();
+
+    let raw = server.exchange(format!(
+        "GET /page.txt HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: {}\r\nConnection: close\r\n\r\n",
+        etag
+    ));
+    // Nothing follows the head of a 304 response
+    assert!(raw.ends_with(b"\r\n\r\n"));
+    let response = parse_responses(&raw, false).remove(0);
+    assert_eq!(response.status, 304);
+    assert_eq!(response.header("ETag"), Some(&*etag));
+
+    let response = server.request("GET", "/page.txt", &[("If-None-Match", "\"other\"")]);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "cached");
+}
+
+#[test]
+fn if_modified_since_compares_with_the_modification_time() {
+    let dir = TempDir::new();
+    let path = dir.write("page.txt", "dated");
+    // Wed, 01 Jan 2020 00:00:00 GMT
+    let modified = UNIX_EPOCH + Duration::from_secs(1_577_836_800);
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    assert_eq!(
+        server.get("/page.txt").header("Last-Modified"),
+        Some("Wed, 01 Jan 2020 00:00:00 GMT")
+    );
+
+    for (since, status) in &[
+        ("Wed, 01 Jan 2020 00:00:00 GMT", 304),
+        ("Thu, 02 Jan 2020 12:00:00 GMT", 304),
+        ("Tue, 31 Dec 2019 23:59:59 GMT", 200),
+    ] {
+        let response = server.request("GET", "/page.txt", &[("If-Modified-Since", since)]);
+        assert_eq!(response.status, *status, "{}", since);
+    }
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use common::{TempDir, TestServer};
+use http_server::ServerBuilder;
+
+#[test]
+fn index_pages_redirect_to_their_directory() {
+    let dir = TempDir::new();
+    dir.write("index.html", "home");
+    dir.write("dir/index.html", "dir");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()).canonical_index(true));
+    for (path, location) in &[("/index.html", "/"), ("/dir/index.html?x=1", "/dir/?x=1")] {
+        let response = server.get(path);
+        assert_eq!(response.status, 301, "{}", path);
+        assert_eq!(response.header("Location"), Some(*location));
+    }
+    assert_eq!(server.get("/").text(), "home");
+    assert_eq!(server.get("/dir/").text(), "dir");
+}
+
+#[test]
+fn directories_redirect_to_their_trailing_slash() {
+    let dir = TempDir::new();
+    dir.write("docs/index.html", "docs");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    for (path, location) in &[
+        ("/docs", "/docs/"),
+        ("/docs?page=2&q=a", "/docs/?page=2&q=a"),
+    ] {
+        let response = server.get(path);
+        assert_eq!(response.status, 301, "{}", path);
+        assert_eq!(response.header("Location"), Some(*location));
+    }
+    assert_eq!(server.get("/docs/").text(), "docs");
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::{TempDir, TestServer};
+use http_server::ServerBuilder;
+
+#[test]
+fn missing_path_is_a_404_without_a_404_page() {
+    let dir = TempDir::new();
+    dir.write("index.html", "<p>Home</p>");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    for path in &["/missing.html", "/missing/", "/nested/missing.txt"] {
+        let response = server.get(path);
+        assert_eq!(response.status, 404, "{}", path);
+        assert!(response.text().contains("<title>404 Not Found</title>"));
+        assert!(response.text().contains(&format!("{} was not found", path)));
+    }
+}
+
+#[test]
+fn custom_404_page_is_used_when_present() {
+    let dir = TempDir::new();
+    dir.write("404.html", "<p>Nothing here</p>");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    let response = server.get("/missing.html");
+    assert_eq!(response.status, 404);
+    assert_eq!(response.text(), "<p>Nothing here</p>");
+    assert!(response
+        .header("Content-Type")
+        .unwrap()
+        .starts_with("text/html"));
+}
+
+#[test]
+fn unreadable_404_page_falls_back_to_the_built_in_page() {
+    let dir = TempDir::new();
+    // A folder in place of the page can't be read as one
+    dir.write("404.html/index.html", "<p>Not a page</p>");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    let response = server.get("/missing.html");
+    assert_eq!(response.status, 404);
+    assert!(response.text().contains("<title>404 Not Found</title>"));
+}
+
+#[test]
+fn error_page_format_follows_the_accept_header() {
+    let dir = TempDir::new();
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+
+    let response = server.request("GET", "/missing", &[("Accept", "application/json")]);
+    assert_eq!(response.status, 404);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    assert!(response.text().starts_with("{\"error\":"));
+    assert!(response.text().ends_with(",\"status\":404}"));
+
+    let response = server.request("GET", "/missing", &[("Accept", "text/html")]);
+    assert_eq!(response.status, 404);
+    assert!(response
+        .header("Content-Type")
+        .unwrap()
+        .starts_with("text/html"));
+    assert!(response.text().contains("<title>404 Not Found</title>"));
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use common::{parse_responses, read_to_close, TempDir, TestServer};
+use http_server::ServerBuilder;
+use std::fs;
+use std::io::{Read, Write};
+
+/// Reads from the stream until a whole response head has arrived.
+fn read_head(stream: &mut impl Read) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+#[test]
+fn upload_gets_continue_before_the_body() {
+    let (serve, uploads) = (TempDir::new(), TempDir::new());
+    let server = TestServer::start(ServerBuilder::new(serve.as_str()).upload_dir(uploads.as_str()));
+    let mut stream = server.connect();
+    stream
+        .write_all(
+            b"PUT /notes.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\
+              Expect: 100-continue\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+    assert!(read_head(&mut stream).starts_with("HTTP/1.1 100 "));
+    stream.write_all(b"hello").unwrap();
+    let responses = parse_responses(&read_to_close(&mut stream), false);
+    assert_eq!(responses[0].status, 201);
+    assert_eq!(
+        fs::read_to_string(uploads.path().join("notes.txt")).unwrap(),
+        "hello"
+    );
+}
+
+#[test]
+fn rejected_upload_gets_no_continue() {
+    let (serve, uploads) = (TempDir::new(), TempDir::new());
+    let server = TestServer::start(
+        ServerBuilder::new(serve.as_str())
+            .upload_dir(uploads.as_str())
+            .auth("user:secret"),
+    );
+    let responses = parse_responses(
+        &server.exchange(
+            "PUT /notes.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\
+             Expect: 100-continue\r\n\r\n",
+        ),
+        false,
+    );
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].status, 401);
+    assert_eq!(responses[0].header("Connection"), Some("close"));
+}
+
+#[test]
+fn unused_body_gets_no_continue() {
+    let dir = TempDir::new();
+    dir.write("index.html", "hello");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    let responses = parse_responses(
+        &server.exchange(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\
+             Expect: 100-continue\r\n\r\n",
+        ),
+        false,
+    );
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].status, 200);
+    assert_eq!(responses[0].header("Connection"), Some("close"));
+}
+
+#[test]
+fn unknown_expectation_is_refused() {
+    let dir = TempDir::new();
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    let response = server.request("GET", "/", &[("Expect", "something-else")]);
+    assert_eq!(response.status, 417);
+}
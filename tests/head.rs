@@ -0,0 +1,126 @@
+mod common;
+
+use common::{parse_responses, TempDir, TestServer};
+use http_server::ServerBuilder;
+
+/// Values of `Accept-Encoding` to try, from none to several encodings
+const ENCODINGS: [&str; 5] = ["", "gzip", "deflate", "br", "br, gzip"];
+
+/// Serves files of several types, a directory without an index and a single-page app
+/// fallback, all large enough to be compressed.
+fn served() -> TempDir {
+    let dir = TempDir::new();
+    let text = "Lorem ipsum dolor sit amet. ".repeat(200);
+    dir.write("index.html", format!("<p>{}</p>", text));
+    dir.write("style.css", format!("p {{ content: '{}'; }}", text));
+    dir.write("data.json", format!("{{\"text\": \"{}\"}}", text));
+    dir.write("notes.txt", &text);
+    // Not compressed, as the format already is
+    dir.write("image.png", vec![7u8; 4096]);
+    dir.write("small.txt", "tiny");
+    for i in 0..50 {
+        dir.write(&format!("listed/file-{}.txt", i), "x");
+    }
+    dir
+}
+
+/// Checks that HEAD reports the framing and encoding of the GET response, with the
+/// Content-Length of its body, for the path with every encoding.
+fn assert_head_matches_get(server: &TestServer, path: &str) {
+    for encoding in &ENCODINGS {
+        let headers: &[(&str, &str)] = if encoding.is_empty() {
+            &[]
+        } else {
+            &[("Accept-Encoding", encoding)]
+        };
+        let get = server.request("GET", path, headers);
+        let head = server.request("HEAD", path, headers);
+        let context = format!("{} with '{}'", path, encoding);
+        assert_eq!(head.status, get.status, "{}", context);
+        assert!(head.body.is_empty(), "{}", context);
+        // Streamed bodies have no length to report, and are chunked for both methods
+        if get.header("Transfer-Encoding").is_none() {
+            assert_eq!(
+                get.header("Content-Length"),
+                Some(get.body.len().to_string().as_str()),
+                "{}",
+                context
+            );
+        }
+        for name in &["Content-Length", "Transfer-Encoding", "Content-Encoding"] {
+            assert_eq!(head.header(name), get.header(name), "{}: {}", context, name);
+        }
+    }
+}
+
+#[test]
+fn head_matches_get_for_files() {
+    let dir = served();
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    for path in &[
+        "/",
+        "/index.html",
+        "/style.css",
+        "/data.json",
+        "/notes.txt",
+        "/image.png",
+        "/small.txt",
+        "/missing.txt",
+    ] {
+        assert_head_matches_get(&server, path);
+    }
+    // The test covers compressed responses too
+    let response = server.request("HEAD", "/notes.txt", &[("Accept-Encoding", "gzip")]);
+    assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+}
+
+#[test]
+fn head_reports_the_compressed_length() {
+    let dir = served();
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    let headers = [("Accept-Encoding", "gzip")];
+    let get = server.request("GET", "/notes.txt", &headers);
+    let head = server.request("HEAD", "/notes.txt", &headers);
+    assert_eq!(head.header("Content-Encoding"), Some("gzip"));
+    let length: usize = head.header("Content-Length").unwrap().parse().unwrap();
+    assert_eq!(length, get.body.len());
+    let plain_length = "Lorem ipsum dolor sit amet. ".len() * 200;
+    assert!(length < plain_length, "{} isn't compressed", length);
+}
+
+#[test]
+fn head_matches_get_for_directory_listings() {
+    let dir = served();
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()).autoindex(true));
+    assert_head_matches_get(&server, "/listed/");
+}
+
+#[test]
+fn head_matches_get_for_the_single_page_app_fallback() {
+    let dir = served();
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()).spa(true));
+    assert_head_matches_get(&server, "/some/route");
+}
+
+#[test]
+fn head_response_has_no_body_before_the_next_response() {
+    let dir = served();
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    let bytes = server.exchange(
+        "HEAD /notes.txt HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         GET /small.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    let head_end = bytes
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap()
+        + 4;
+    let head = parse_responses(&bytes[..head_end], true).remove(0);
+    assert_eq!(head.status, 200);
+    assert_eq!(head.header("Connection"), Some("keep-alive"));
+    assert_ne!(head.header("Content-Length"), Some("0"));
+    // The next response follows the headers right away
+    let get = parse_responses(&bytes[head_end..], false).remove(0);
+    assert_eq!(get.status, 200);
+    assert_eq!(get.text(), "tiny");
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use common::{parse_responses, read_to_close, Response, TempDir, TestServer};
+use http_server::ServerBuilder;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+/// Serves two small pages.
+fn pages_server() -> (TempDir, TestServer) {
+    let dir = TempDir::new();
+    dir.write("one.txt", "first page");
+    dir.write("two.txt", "second page");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    (dir, server)
+}
+
+/// Reads a single response with a Content-Length from the stream, leaving the connection
+/// open.
+fn read_response(stream: &mut TcpStream) -> Response {
+    let mut bytes = Vec::new();
+    let mut byte = [0; 1];
+    while !bytes.ends_with(b"\r\n\r\n") {
+        assert_eq!(stream.read(&mut byte).unwrap(), 1, "connection closed");
+        bytes.push(byte[0]);
+    }
+    let head = parse_responses(&bytes, true).remove(0);
+    let length: usize = head.header("Content-Length").unwrap().parse().unwrap();
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).unwrap();
+    Response { body, ..head }
+}
+
+#[test]
+fn connection_is_reused_for_later_requests() {
+    let (_dir, server) = pages_server();
+    let mut stream = server.connect();
+    for (path, text) in &[("/one.txt", "first page"), ("/two.txt", "second page")] {
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).unwrap();
+        let response = read_response(&mut stream);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Connection"), Some("keep-alive"));
+        assert_eq!(response.text(), *text);
+    }
+}
+
+#[test]
+fn connection_is_closed_after_the_maximum_requests() {
+    let dir = TempDir::new();
+    dir.write("one.txt", "first page");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()).keep_alive_max(3));
+    let request = "GET /one.txt HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    let mut stream = server.connect();
+    for remaining in &["2", "1"] {
+        stream.write_all(request.as_bytes()).unwrap();
+        let response = read_response(&mut stream);
+        assert_eq!(response.header("Connection"), Some("keep-alive"));
+        let keep_alive = format!("timeout=5, max={}", remaining);
+        assert_eq!(response.header("Keep-Alive"), Some(&*keep_alive));
+    }
+
+    // The third request is the last one answered, even with another one already sent
+    stream.write_all(request.repeat(2).as_bytes()).unwrap();
+    let responses = parse_responses(&read_to_close(&mut stream), false);
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].status, 200);
+    assert_eq!(responses[0].header("Connection"), Some("close"));
+    assert_eq!(responses[0].header("Keep-Alive"), None);
+}
+
+#[test]
+fn pipelined_requests_are_answered_in_order() {
+    let (_dir, server) = pages_server();
+    let bytes = server.exchange(
+        "GET /one.txt HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         GET /missing.txt HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         GET /two.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    let responses = parse_responses(&bytes, false);
+    let statuses: Vec<u16> = responses.iter().map(|response| response.status).collect();
+    assert_eq!(statuses, [200, 404, 200]);
+    assert_eq!(responses[0].text(), "first page");
+    assert_eq!(responses[0].header("Connection"), Some("keep-alive"));
+    assert_eq!(responses[2].text(), "second page");
+    assert_eq!(responses[2].header("Connection"), Some("close"));
+}
+
+#[test]
+fn connection_close_ends_the_connection() {
+    let (_dir, server) = pages_server();
+    let bytes = server.exchange(
+        "GET /one.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n\
+         GET /two.txt HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    let responses = parse_responses(&bytes, false);
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].header("Connection"), Some("close"));
+}
+
+#[test]
+fn http_1_0_connections_close_unless_kept_alive() {
+    let (_dir, server) = pages_server();
+    let bytes = server.exchange(
+        "GET /one.txt HTTP/1.0\r\n\r\n\
+         GET /two.txt HTTP/1.0\r\n\r\n",
+    );
+    assert_eq!(parse_responses(&bytes, false).len(), 1);
+
+    let mut stream = server.connect();
+    stream
+        .write_all(b"GET /one.txt HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+        .unwrap();
+    let response = read_response(&mut stream);
+    assert_eq!(response.header("Connection"), Some("keep-alive"));
+    stream.write_all(b"GET /two.txt HTTP/1.0\r\n\r\n").unwrap();
+    let responses = parse_responses(&read_to_close(&mut stream), false);
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].text(), "second page");
+}
+
+#[test]
+fn unused_request_bodies_are_skipped() {
+    let (_dir, server) = pages_server();
+    let bytes = server.exchange(
+        "POST /one.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 25\r\n\r\n\
+         GET /one.txt HTTP/1.1\r\n\r\n\
+         GET /two.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    let responses = parse_responses(&bytes, false);
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0].status, 405);
+    // The body looked like a request, but isn't taken for one
+    assert_eq!(responses[1].text(), "second page");
+}
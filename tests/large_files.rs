@@ -0,0 +1,26 @@
+mod common;
+
+use common::{TempDir, TestServer};
+use http_server::ServerBuilder;
+
+/// Three mebibytes, above the size from which files are streamed from disk
+const SIZE: usize = 3 * 1024 * 1024;
+
+#[test]
+fn large_file_is_streamed_intact() {
+    let dir = TempDir::new();
+    // A pattern that doesn't line up with the copy buffer, so misplaced chunks would show
+    let contents: Vec<u8> = (0..SIZE).map(|i| (i % 251) as u8).collect();
+    dir.write("video.bin", &contents);
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+
+    let response = server.get("/video.bin");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Length"), Some(&*SIZE.to_string()));
+    assert_eq!(response.header("Transfer-Encoding"), None);
+    assert_eq!(response.body.len(), SIZE);
+    assert!(
+        response.body == contents,
+        "streamed bytes differ from the file"
+    );
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::{parse_responses, read_to_close, TempDir, TestServer};
+use http_server::ServerBuilder;
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+
+/// Serves a single page.
+fn page_server() -> (TempDir, TestServer) {
+    let dir = TempDir::new();
+    dir.write("page.txt", "page");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    (dir, server)
+}
+
+#[test]
+fn headers_larger_than_one_read_are_accepted() {
+    let (_dir, server) = page_server();
+    let request = format!(
+        "GET /page.txt HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\n\
+         Connection: close\r\n\r\n",
+        "a".repeat(6000)
+    );
+    let response = parse_responses(&server.exchange(&request), false).remove(0);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "page");
+}
+
+#[test]
+fn headers_arriving_in_pieces_are_put_together() {
+    let (_dir, server) = page_server();
+    let request = format!(
+        "GET /page.txt HTTP/1.1\r\nHost: localhost\r\nX-Padding: {}\r\n\
+         Connection: close\r\n\r\n",
+        "b".repeat(3000)
+    );
+    let mut stream = server.connect();
+    for piece in request.as_bytes().chunks(700) {
+        stream.write_all(piece).unwrap();
+        stream.flush().unwrap();
+        thread::sleep(Duration::from_millis(20));
+    }
+    let response = parse_responses(&read_to_close(&mut stream), false).remove(0);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "page");
+}
+
+#[test]
+fn headers_over_the_limit_are_rejected() {
+    let dir = TempDir::new();
+    dir.write("page.txt", "page");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()).max_header_size(2048));
+    let request = format!(
+        "GET /page.txt HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\n\r\n",
+        "c".repeat(4000)
+    );
+    let response = parse_responses(&server.exchange(&request), false).remove(0);
+    assert_eq!(response.status, 400);
+    assert_eq!(response.header("Connection"), Some("close"));
+}
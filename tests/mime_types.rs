@@ -0,0 +1,25 @@
+mod common;
+
+use common::{TempDir, TestServer};
+use http_server::ServerBuilder;
+
+#[test]
+fn configured_types_are_served() {
+    let served = TempDir::new();
+    served.write("data.foo", "foo");
+    served.write("page.html", "<p>page</p>");
+    let config = TempDir::new();
+    let types = config.write("mime.types", "# Local types\nfoo application/x-foo\n");
+    let server =
+        TestServer::start(ServerBuilder::new(served.as_str()).mime_types(types.to_str().unwrap()));
+
+    let response = server.get("/data.foo");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Type"), Some("application/x-foo"));
+    // Types missing from the file keep their built-in values
+    assert!(server
+        .get("/page.html")
+        .header("Content-Type")
+        .unwrap()
+        .starts_with("text/html"));
+}
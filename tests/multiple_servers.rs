@@ -0,0 +1,46 @@
+mod common;
+
+use common::{TempDir, TestServer};
+use http_server::ServerBuilder;
+
+#[test]
+fn servers_keep_their_own_settings() {
+    let dir = TempDir::new();
+    dir.write("page.txt", "text");
+    dir.write("archive.zip", "zip");
+    let plain = TestServer::start(ServerBuilder::new(dir.as_str()));
+    let custom = TestServer::start(
+        ServerBuilder::new(dir.as_str())
+            .server_header(None)
+            .charset("ISO-8859-1")
+            .no_cache(true)
+            .nosniff(true)
+            .download_types(&["zip"]),
+    );
+
+    let response = plain.get("/page.txt");
+    assert!(response.header("Server").is_some());
+    assert_eq!(
+        response.header("Content-Type"),
+        Some("text/plain; charset=UTF-8")
+    );
+    assert_ne!(response.header("Cache-Control"), Some("no-store"));
+    assert_eq!(response.header("X-Content-Type-Options"), None);
+    assert_eq!(
+        plain.get("/archive.zip").header("Content-Disposition"),
+        None
+    );
+
+    let response = custom.get("/page.txt");
+    assert_eq!(response.header("Server"), None);
+    assert_eq!(
+        response.header("Content-Type"),
+        Some("text/plain; charset=ISO-8859-1")
+    );
+    assert_eq!(response.header("Cache-Control"), Some("no-store"));
+    assert_eq!(response.header("X-Content-Type-Options"), Some("nosniff"));
+    assert!(custom
+        .get("/archive.zip")
+        .header("Content-Disposition")
+        .is_some());
+}
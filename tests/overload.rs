@@ -0,0 +1,67 @@
+mod common;
+
+use common::{parse_responses, read_to_close, TempDir, TestServer};
+use http_server::ServerBuilder;
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+/// Opens a connection that has started a request but not finished it, keeping whichever
+/// worker picks it up busy. Waits long enough for the server to accept it.
+fn unfinished_request(server: &TestServer) -> TcpStream {
+    let mut stream = server.connect();
+    stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    thread::sleep(Duration::from_millis(300));
+    stream
+}
+
+#[test]
+fn full_queue_turns_connections_away() {
+    let dir = TempDir::new();
+    let server = TestServer::start(
+        ServerBuilder::new(dir.as_str())
+            .threads(1)
+            .queue_size(1)
+            .retry_after(Duration::from_secs(7)),
+    );
+    // One connection occupies the worker and the next one waits in the queue
+    let busy = unfinished_request(&server);
+    let mut queued = unfinished_request(&server);
+
+    let mut stream = server.connect();
+    let response = parse_responses(&read_to_close(&mut stream), false).remove(0);
+    assert_eq!(response.status, 503);
+    assert_eq!(response.header("Retry-After"), Some("7"));
+
+    // The queued connection is still handled once the worker is free
+    drop(busy);
+    queued
+        .write_all(b"Host: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let response = parse_responses(&read_to_close(&mut queued), false).remove(0);
+    assert_ne!(response.status, 503);
+}
+
+#[test]
+fn open_connections_are_limited() {
+    let dir = TempDir::new();
+    dir.write("page.txt", "page");
+    let server = TestServer::start(
+        ServerBuilder::new(dir.as_str())
+            .threads(4)
+            .max_connections(2),
+    );
+    let held: Vec<TcpStream> = (0..2).map(|_| unfinished_request(&server)).collect();
+
+    let mut stream = server.connect();
+    let response = parse_responses(&read_to_close(&mut stream), false).remove(0);
+    assert_eq!(response.status, 503);
+
+    // Closed connections stop counting once their handlers return
+    drop(held);
+    thread::sleep(Duration::from_millis(300));
+    for _ in 0..3 {
+        assert_eq!(server.get("/page.txt").status, 200);
+    }
+}
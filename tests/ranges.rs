@@ -0,0 +1,48 @@
+mod common;
+
+use common::{TempDir, TestServer};
+use http_server::ServerBuilder;
+
+/// Serves a file with the digits from 0 to 9.
+fn digits_server() -> (TempDir, TestServer) {
+    let dir = TempDir::new();
+    dir.write("digits.txt", "0123456789");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    (dir, server)
+}
+
+#[test]
+fn requested_range_is_sent_as_partial_content() {
+    let (_dir, server) = digits_server();
+    for (range, content_range, body) in &[
+        ("bytes=2-5", "bytes 2-5/10", "2345"),
+        ("bytes=7-", "bytes 7-9/10", "789"),
+        ("bytes=-3", "bytes 7-9/10", "789"),
+        ("bytes=8-100", "bytes 8-9/10", "89"),
+    ] {
+        let response = server.request("GET", "/digits.txt", &[("Range", range)]);
+        assert_eq!(response.status, 206, "{}", range);
+        assert_eq!(response.header("Content-Range"), Some(*content_range));
+        assert_eq!(response.text(), *body);
+    }
+}
+
+#[test]
+fn unsatisfiable_range_is_a_416() {
+    let (_dir, server) = digits_server();
+    let response = server.request("GET", "/digits.txt", &[("Range", "bytes=10-")]);
+    assert_eq!(response.status, 416);
+    assert_eq!(response.header("Content-Range"), Some("bytes */10"));
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn unsupported_ranges_get_the_whole_file() {
+    let (_dir, server) = digits_server();
+    for range in &["bytes=1-2,4-5", "bytes=5-2", "lines=1-2"] {
+        let response = server.request("GET", "/digits.txt", &[("Range", range)]);
+        assert_eq!(response.status, 200, "{}", range);
+        assert_eq!(response.header("Accept-Ranges"), Some("bytes"));
+        assert_eq!(response.text(), "0123456789");
+    }
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use common::{TempDir, TestServer};
+use http_server::ServerBuilder;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+#[test]
+fn stopping_the_handle_stops_the_server() {
+    let dir = TempDir::new();
+    dir.write("index.html", "hello");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()));
+    assert_eq!(server.get("/").status, 200);
+    let address = server.address;
+    let started = Instant::now();
+    server.stop().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(TcpStream::connect(address).is_err());
+}
+
+#[test]
+fn stopping_one_server_leaves_another_running() {
+    let dir = TempDir::new();
+    dir.write("index.html", "hello");
+    let first = TestServer::start(ServerBuilder::new(dir.as_str()));
+    let second = TestServer::start(ServerBuilder::new(dir.as_str()));
+    first.stop().unwrap();
+    assert_eq!(second.get("/").status, 200);
+    // A server started after another one was stopped isn't stopped from the start
+    let third = TestServer::start(ServerBuilder::new(dir.as_str()));
+    assert_eq!(third.get("/").text(), "hello");
+}
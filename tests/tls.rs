@@ -0,0 +1,98 @@
+mod common;
+
+use common::{parse_responses, TempDir, TestServer};
+use http_server::ServerBuilder;
+use rustls::crypto::ring::default_provider;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+/// A self-signed certificate for `localhost`, and its private key
+const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/localhost.crt");
+const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/localhost.key");
+
+/// Serves a directory with one file over TLS.
+fn tls_server() -> (TempDir, TestServer) {
+    let dir = TempDir::new();
+    dir.write("hello.txt", "Hello over TLS");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()).tls(CERT, KEY));
+    (dir, server)
+}
+
+/// Connects to the server over TLS, trusting only the test certificate.
+fn connect(server: &TestServer) -> StreamOwned<ClientConnection, TcpStream> {
+    let mut roots = RootCertStore::empty();
+    let pem = std::fs::read(CERT).unwrap();
+    for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from("localhost").unwrap();
+    let connection = ClientConnection::new(Arc::new(config), name).unwrap();
+    StreamOwned::new(connection, server.connect())
+}
+
+/// Sends the raw request over TLS and returns everything the server sent before closing
+/// the connection.
+fn exchange(server: &TestServer, request: &str) -> Vec<u8> {
+    let mut stream = connect(server);
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut bytes = Vec::new();
+    // Fails if the server closes without telling the client the data is complete
+    stream.read_to_end(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn file_is_fetched_over_tls() {
+    let (_dir, server) = tls_server();
+    let bytes = exchange(
+        &server,
+        "GET /hello.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    let response = parse_responses(&bytes, false).remove(0);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "Hello over TLS");
+}
+
+#[test]
+fn tls_connections_are_kept_alive() {
+    let (_dir, server) = tls_server();
+    let bytes = exchange(
+        &server,
+        "GET /hello.txt HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         GET /missing.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    let responses = parse_responses(&bytes, false);
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0].status, 200);
+    assert_eq!(responses[0].header("Connection"), Some("keep-alive"));
+    assert_eq!(responses[1].status, 404);
+}
+
+#[test]
+fn plain_http_gets_no_response() {
+    let (_dir, server) = tls_server();
+    let bytes = server.exchange("GET /hello.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(!bytes.starts_with(b"HTTP/"));
+}
+
+#[test]
+fn server_does_not_start_without_a_usable_key() {
+    let dir = TempDir::new();
+    let key = dir.write("empty.key", "");
+    let config = ServerBuilder::new(dir.as_str())
+        .port(0)
+        .log_level(None)
+        .tls(CERT, key.to_str().unwrap())
+        .build();
+    let err = http_server::run_with(config, |_| panic!("server started")).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use common::{TempDir, TestServer};
+use http_server::ServerBuilder;
+
+#[test]
+fn transformers_apply_in_order() {
+    let dir = TempDir::new();
+    dir.write("index.html", "hello");
+    let server = TestServer::start(
+        ServerBuilder::new(dir.as_str())
+            .transformer(|_, response| response.set_header("X-Order", String::from("first")))
+            .transformer(|request, response| {
+                let previous = response.header("X-Order").unwrap_or("").to_string();
+                response.set_header("X-Order", format!("{} second {}", previous, request.path));
+            }),
+    );
+    assert_eq!(server.get("/").header("X-Order"), Some("first second /"));
+    // Error responses are transformed too
+    assert_eq!(
+        server.get("/missing").header("X-Order"),
+        Some("first second /missing")
+    );
+}
+
+#[test]
+fn servers_apply_only_their_own_transformers() {
+    let dir = TempDir::new();
+    dir.write("index.html", "hello");
+    let tagged = TestServer::start(
+        ServerBuilder::new(dir.as_str())
+            .transformer(|_, response| response.set_header("X-Tag", String::from("tagged"))),
+    );
+    let plain = TestServer::start(ServerBuilder::new(dir.as_str()));
+    assert_eq!(tagged.get("/").header("X-Tag"), Some("tagged"));
+    assert_eq!(plain.get("/").header("X-Tag"), None);
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use common::{TempDir, TestServer};
+use http_server::ServerBuilder;
+
+#[test]
+fn files_outside_the_served_folder_are_not_served() {
+    let root = TempDir::new();
+    root.write("secret.txt", "top secret");
+    root.write("public/index.html", "<p>Home</p>");
+    let public = root.path().join("public");
+    let server = TestServer::start(ServerBuilder::new(public.to_str().unwrap()));
+    for path in &[
+        "/../secret.txt",
+        "/%2e%2e/secret.txt",
+        "/%2E%2E%2Fsecret.txt",
+        "/..%2fsecret.txt",
+        "/docs/../../secret.txt",
+        "/.%2e/.%2e/secret.txt",
+        "//etc/passwd",
+    ] {
+        let bytes = server.exchange(format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        ));
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(!text.starts_with("HTTP/1.1 200"), "{}: {}", path, text);
+        assert!(!text.contains("top secret"), "{}", path);
+        assert!(!text.contains("root:"), "{}", path);
+    }
+    assert_eq!(server.get("/index.html").status, 200);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::{read_to_close, TempDir, TestServer};
+use http_server::ServerBuilder;
+use std::fs;
+use std::io::Write;
+use std::net::Shutdown;
+
+fn upload_server(serve: &TempDir, uploads: &TempDir) -> TestServer {
+    TestServer::start(ServerBuilder::new(serve.as_str()).upload_dir(uploads.as_str()))
+}
+
+fn put(server: &TestServer, path: &str, body: &str) -> u16 {
+    let request = format!(
+        "PUT {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        body.len(),
+        body
+    );
+    common::parse_responses(&server.exchange(request), false)[0].status
+}
+
+#[test]
+fn upload_creates_and_replaces_files() {
+    let (serve, uploads) = (TempDir::new(), TempDir::new());
+    let server = upload_server(&serve, &uploads);
+    assert_eq!(put(&server, "/notes.txt", "first"), 201);
+    assert_eq!(put(&server, "/notes.txt", "second"), 204);
+    assert_eq!(
+        fs::read_to_string(uploads.path().join("notes.txt")).unwrap(),
+        "second"
+    );
+    assert_eq!(fs::read_dir(uploads.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn aborted_upload_keeps_the_existing_file() {
+    let (serve, uploads) = (TempDir::new(), TempDir::new());
+    uploads.write("notes.txt", "original");
+    let server = upload_server(&serve, &uploads);
+    let mut stream = server.connect();
+    stream
+        .write_all(
+            b"PUT /notes.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100\r\n\r\npartial",
+        )
+        .unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    read_to_close(&mut stream);
+    assert_eq!(
+        fs::read_to_string(uploads.path().join("notes.txt")).unwrap(),
+        "original"
+    );
+    // Nothing is left behind of the incomplete upload
+    let names: Vec<_> = fs::read_dir(uploads.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["notes.txt"]);
+}
+
+#[test]
+fn upload_outside_the_folder_is_refused() {
+    let (serve, uploads) = (TempDir::new(), TempDir::new());
+    let server = upload_server(&serve, &uploads);
+    assert_eq!(put(&server, "/../escape.txt", "x"), 400);
+    assert_eq!(put(&server, "/missing/file.txt", "x"), 400);
+}
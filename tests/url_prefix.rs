@@ -0,0 +1,35 @@
+mod common;
+
+use common::{parse_responses, TempDir, TestServer};
+use http_server::ServerBuilder;
+
+#[test]
+fn paths_are_served_under_the_prefix() {
+    let dir = TempDir::new();
+    dir.write("page.html", "page");
+    let server = TestServer::start(ServerBuilder::new(dir.as_str()).url_prefix("/app"));
+    assert_eq!(server.get("/app/page.html").text(), "page");
+    assert_eq!(server.get("/page.html").status, 404);
+}
+
+#[test]
+fn upload_outside_the_prefix_closes_the_connection() {
+    let (serve, uploads) = (TempDir::new(), TempDir::new());
+    serve.write("secret.html", "secret");
+    let server = TestServer::start(
+        ServerBuilder::new(serve.as_str())
+            .url_prefix("/app")
+            .upload_dir(uploads.as_str()),
+    );
+    // The body is a request of its own, which must not be answered
+    let smuggled = "GET /app/secret.html HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    let request = format!(
+        "PUT /other.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+        smuggled.len(),
+        smuggled
+    );
+    let responses = parse_responses(&server.exchange(request), false);
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].status, 404);
+    assert_eq!(responses[0].header("Connection"), Some("close"));
+}
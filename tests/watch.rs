@@ -0,0 +1,88 @@
+mod common;
+
+use common::{Response, TempDir, TestServer};
+use http_server::ServerBuilder;
+use std::fs::{self, File};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Requests the path until the response passes the check, failing after a few seconds.
+fn wait_for(server: &TestServer, path: &str, check: impl Fn(&Response) -> bool) -> Response {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let response = server.get(path);
+        if check(&response) {
+            return response;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "change not applied: {:?}",
+            response
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn modified_redirects_take_effect() {
+    let served = TempDir::new();
+    let config = TempDir::new();
+    let rules = config.write("redirects.txt", "/old /first\n");
+    let server = TestServer::start(
+        ServerBuilder::new(served.as_str())
+            .redirects(rules.to_str().unwrap())
+            .watch(true),
+    );
+    let response = server.get("/old");
+    assert_eq!(response.status, 301);
+    assert_eq!(response.header("Location"), Some("/first"));
+
+    fs::write(&rules, "/old /second 302\n").unwrap();
+    let response = wait_for(&server, "/old", |response| response.status == 302);
+    assert_eq!(response.header("Location"), Some("/second"));
+}
+
+#[test]
+fn replaced_mime_types_take_effect() {
+    let served = TempDir::new();
+    served.write("data.foo", "foo");
+    let config = TempDir::new();
+    let types = config.write("mime.types", "foo application/x-first\n");
+    let server = TestServer::start(
+        ServerBuilder::new(served.as_str())
+            .mime_types(types.to_str().unwrap())
+            .watch(true),
+    );
+    let response = server.get("/data.foo");
+    assert_eq!(response.header("Content-Type"), Some("application/x-first"));
+
+    // Saved the way many editors do, by renaming a new file over the old one
+    let replacement = config.write("mime.types.new", "foo application/x-second\n");
+    fs::rename(&replacement, &types).unwrap();
+    wait_for(&server, "/data.foo", |response| {
+        response.header("Content-Type") == Some("application/x-second")
+    });
+}
+
+#[test]
+fn changed_files_are_dropped_from_the_cache() {
+    let served = TempDir::new();
+    let path = served.write("page.txt", "first");
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    let server = TestServer::start(
+        ServerBuilder::new(served.as_str())
+            .cache_size(1024 * 1024)
+            .watch(true),
+    );
+    assert_eq!(server.get("/page.txt").text(), "first");
+
+    // Without the watch, the cache couldn't tell the file changed
+    fs::write(&path, "again").unwrap();
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    wait_for(&server, "/page.txt", |response| response.text() == "again");
+}